@@ -0,0 +1,96 @@
+/*
+   Copyright 2023 James Forster
+
+   This file is part of gap_query_interval_tree.
+
+   gap_query_interval_tree is free software: you can redistribute it
+   and/or modify it under the terms of the GNU Affero General Public
+   License as published by the Free Software Foundation, either
+   version 3 of the License, or (at your option) any later version.
+
+   gap_query_interval_tree is distributed in the hope that it will be
+   useful, but WITHOUT ANY WARRANTY; without even the implied warranty
+   of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+   Affero General Public License for more details.
+
+   You should have received a copy of the GNU Affero General Public
+   License along with gap_query_interval_tree. If not, see
+   <https://www.gnu.org/licenses/>.
+*/
+
+//! Compares [`GapQueryIntervalTree::gap_query()`] with
+//! [`GapQueryIntervalTree::gap_query_trimmed()`] on trees with many
+//! short free segments either side of the query interval, which is the
+//! worst case for expanding the end gaps.
+
+#![feature(test)]
+
+extern crate test;
+
+use std::collections::BTreeSet;
+use std::hint::black_box;
+
+use gap_query_interval_tree::{
+    GapQueryIntervalTree, NaiveGapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+};
+use nodit::interval::ii;
+use nodit::Interval;
+use test::Bencher;
+
+const INTERVALS: u32 = 10_000;
+
+fn fill<T>(tree: &mut T)
+where
+    T: GapQueryIntervalTree<u32, Interval<u32>, u32>,
+{
+    //every other point so that a gap-query with the same identifier
+    //is one gap made up of many segments, expanding which walks the
+    //whole tree
+    for i in 0..INTERVALS {
+        tree.insert(BTreeSet::from([0]), ii(i * 2, i * 2));
+    }
+}
+
+fn query<T>(bencher: &mut Bencher, tree: &T, trimmed: bool)
+where
+    T: GapQueryIntervalTree<u32, Interval<u32>, u32>,
+{
+    let middle = INTERVALS;
+
+    bencher.iter(|| {
+        let interval = black_box(ii(middle - 50, middle + 50));
+        if trimmed {
+            tree.gap_query_trimmed(Some(0), interval)
+        } else {
+            tree.gap_query(Some(0), interval)
+        }
+    });
+}
+
+#[bench]
+fn no_gaps_ref_gap_query(bencher: &mut Bencher) {
+    let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    fill(&mut tree);
+    query(bencher, &tree, false);
+}
+
+#[bench]
+fn no_gaps_ref_gap_query_trimmed(bencher: &mut Bencher) {
+    let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    fill(&mut tree);
+    query(bencher, &tree, true);
+}
+
+#[bench]
+fn naive_gap_query(bencher: &mut Bencher) {
+    let mut tree = NaiveGapQueryIntervalTree::new();
+    fill(&mut tree);
+    query(bencher, &tree, false);
+}
+
+#[bench]
+fn naive_gap_query_trimmed(bencher: &mut Bencher) {
+    let mut tree = NaiveGapQueryIntervalTree::new();
+    fill(&mut tree);
+    query(bencher, &tree, true);
+}
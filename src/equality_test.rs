@@ -18,12 +18,12 @@
    <https://www.gnu.org/licenses/>.
 */
 
-use alloc::collections::BTreeSet;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::vec::Vec;
 use core::fmt::Debug;
 use nodit::{IntervalType, PointType};
 
-use crate::interface::GapQueryIntervalTree;
+use crate::interface::{GapKind, GapQueryIntervalTree};
 use crate::naive::NaiveGapQueryIntervalTree;
 use crate::no_gaps_ref::NoGapsRefGapQueryIntervalTree;
 use crate::IdType;
@@ -34,6 +34,29 @@ pub struct EqualityTestGapQueryIntervalTree<I, K, D> {
     no_gaps_ref: NoGapsRefGapQueryIntervalTree<I, K, D>,
 }
 
+impl<I, K, D> Default for EqualityTestGapQueryIntervalTree<I, K, D>
+where
+    I: PointType,
+    K: IntervalType<I>,
+{
+    fn default() -> Self {
+        Self {
+            naive: NaiveGapQueryIntervalTree::new(),
+            no_gaps_ref: NoGapsRefGapQueryIntervalTree::new(),
+        }
+    }
+}
+
+impl<I, K, D> EqualityTestGapQueryIntervalTree<I, K, D>
+where
+    I: PointType,
+    K: IntervalType<I>,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
 impl<I, K, D> EqualityTestGapQueryIntervalTree<I, K, D>
 where
     D: Eq + Ord + Clone + Copy + Debug,
@@ -63,6 +86,32 @@ where
         result1
     }
 
+    fn gap_query_trimmed<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        let result1 = self.naive.gap_query_trimmed(with_identifier, interval);
+        let result2 = self
+            .no_gaps_ref
+            .gap_query_trimmed(with_identifier, interval);
+
+        assert_eq!(result1, result2);
+
+        result1
+    }
+
+    fn busy_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        let result1 = self.naive.busy_query(with_identifier, interval);
+        let result2 = self.no_gaps_ref.busy_query(with_identifier, interval);
+
+        assert_eq!(result1, result2);
+
+        result1
+    }
+
     fn insert(&mut self, identifiers: BTreeSet<D>, interval: K) {
         self.naive.insert(identifiers.clone(), interval);
         self.no_gaps_ref.insert(identifiers, interval);
@@ -70,6 +119,39 @@ where
         self.assert_eq();
     }
 
+    fn insert_soft(&mut self, identifiers: BTreeSet<D>, interval: K) {
+        self.naive.insert_soft(identifiers.clone(), interval);
+        self.no_gaps_ref.insert_soft(identifiers, interval);
+
+        self.assert_eq();
+    }
+
+    fn soft_gap_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        let result1 = self.naive.soft_gap_query(with_identifier, interval);
+        let result2 = self.no_gaps_ref.soft_gap_query(with_identifier, interval);
+
+        assert_eq!(result1, result2);
+
+        result1
+    }
+
+    fn tentative_gap_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<(K, GapKind)>
+    where
+        Q: IntervalType<I>,
+    {
+        let result1 = self.naive.tentative_gap_query(with_identifier, interval);
+        let result2 = self
+            .no_gaps_ref
+            .tentative_gap_query(with_identifier, interval);
+
+        assert_eq!(result1, result2);
+
+        result1
+    }
+
     fn cut<Q>(&mut self, with_identifiers: Option<BTreeSet<D>>, interval: Q)
     where
         Q: IntervalType<I>,
@@ -87,6 +169,18 @@ where
         self.assert_eq();
     }
 
+    fn gaps_for_all_identifiers<Q>(&self, interval: Q) -> BTreeMap<D, Vec<K>>
+    where
+        Q: IntervalType<I>,
+    {
+        let result1 = self.naive.gaps_for_all_identifiers(interval);
+        let result2 = self.no_gaps_ref.gaps_for_all_identifiers(interval);
+
+        assert_eq!(result1, result2);
+
+        result1
+    }
+
     fn identifiers_at_point(&self, at_point: I) -> BTreeSet<D> {
         let result1 = self.naive.identifiers_at_point(at_point);
         let result2 = self.no_gaps_ref.identifiers_at_point(at_point);
@@ -95,4 +189,82 @@ where
 
         result1
     }
+
+    fn depth_profile<Q>(&self, interval: Q) -> impl Iterator<Item = (K, usize)>
+    where
+        Q: IntervalType<I>,
+    {
+        let result1 = self.naive.depth_profile(interval).collect::<Vec<_>>();
+        let result2 = self.no_gaps_ref.depth_profile(interval).collect::<Vec<_>>();
+
+        assert_eq!(result1, result2);
+
+        result1.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeSet;
+
+    use nodit::interval::ii;
+    use nodit::Interval;
+
+    use super::EqualityTestGapQueryIntervalTree;
+    use crate::interface::GapQueryIntervalTree;
+
+    /// A linear congruential generator so that the test is
+    /// reproducible without any extra dependencies.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            //the high bits are the most random
+            (self.0 >> 33) % n
+        }
+        fn identifiers(&mut self) -> BTreeSet<u8> {
+            (0..=self.below(3)).map(|_| self.below(4) as u8).collect()
+        }
+        fn interval(&mut self) -> Interval<i8> {
+            let start = self.below(60) as i8 - 30;
+            ii(start, start + self.below(15) as i8)
+        }
+    }
+
+    #[test]
+    fn random_operations_agree() {
+        let mut rng = Rng(42);
+
+        for _ in 0..300 {
+            let mut tree = EqualityTestGapQueryIntervalTree::<i8, Interval<i8>, u8>::new();
+
+            for _ in 0..20 {
+                //every operation asserts that both trees agree
+                match rng.below(3) {
+                    0 => tree.insert(rng.identifiers(), rng.interval()),
+                    1 => tree.insert_soft(rng.identifiers(), rng.interval()),
+                    _ => {
+                        let identifiers = (rng.below(3) != 0).then(|| rng.identifiers());
+                        tree.cut(identifiers, rng.interval());
+                    }
+                }
+
+                for _ in 0..5 {
+                    let with_identifier = (rng.below(2) == 0).then(|| rng.below(4) as u8);
+                    let interval = rng.interval();
+
+                    tree.gap_query(with_identifier, interval);
+                    tree.gap_query_trimmed(with_identifier, interval);
+                    tree.busy_query(with_identifier, interval);
+                    tree.tentative_gap_query(with_identifier, interval);
+                    tree.depth_profile(interval).for_each(drop);
+                    tree.gaps_for_all_identifiers(interval);
+                }
+            }
+        }
+    }
 }
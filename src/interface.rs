@@ -18,7 +18,7 @@
    <https://www.gnu.org/licenses/>.
 */
 
-use alloc::collections::BTreeSet;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::vec::Vec;
 
 use nodit::{
@@ -26,6 +26,19 @@ use nodit::{
     {IntervalType, PointType},
 };
 
+use crate::point_arithmetic::PointArithmetic;
+
+/// Whether a gap returned by
+/// [`tentative_gap_query()`](GapQueryIntervalTree::tentative_gap_query)
+/// is truly free or only free of hard intervals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GapKind {
+    /// No other identifier is present.
+    Free,
+    /// Other identifiers are present but only softly.
+    Tentative,
+}
+
 pub trait GapQueryIntervalTree<I, K, D> {
     //optimisation make this a iterator not a vec to save allocation
     //
@@ -54,6 +67,64 @@ pub trait GapQueryIntervalTree<I, K, D> {
     where
         Q: IntervalType<I>;
 
+    /// Like [`gap_query()`](GapQueryIntervalTree::gap_query) but the
+    /// gaps are trimmed to the given interval.
+    ///
+    /// This is cheaper than
+    /// [`gap_query()`](GapQueryIntervalTree::gap_query) as the gaps at
+    /// either end of the interval do not need to be expanded.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    ///
+    /// assert_eq!(
+    /// 	tree.gap_query_trimmed(None, ii(0, 9)),
+    /// 	Vec::from([ii(0, 2), ii(7, 9)])
+    /// );
+    /// ```
+    fn gap_query_trimmed<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>;
+
+    /// Gets the maximally-sized busy intervals that overlap the
+    /// given interval for the given identifier if one is given, these
+    /// are the intervals between the gaps returned by
+    /// [`gap_query()`](GapQueryIntervalTree::gap_query).
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(5, 28));
+    ///
+    /// assert_eq!(
+    /// 	tree.busy_query(None, ii(0, 10)),
+    /// 	Vec::from([ii(3, 28)])
+    /// );
+    /// assert_eq!(
+    /// 	tree.busy_query(Some(5), ii(0, 10)),
+    /// 	Vec::from([ii(5, 28)])
+    /// );
+    /// ```
+    fn busy_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>;
+
     /// Inserts an interval into the collection for the given
     /// identifiers.
     #[doc=include_str!("../images/insertion.svg")]
@@ -72,6 +143,96 @@ pub trait GapQueryIntervalTree<I, K, D> {
     /// ```
     fn insert(&mut self, identifiers: BTreeSet<D>, interval: K);
 
+    /// Inserts a soft interval into the collection for the given
+    /// identifiers, this is a tentative hold which is treated as
+    /// occupied by every query except
+    /// [`soft_gap_query()`](GapQueryIntervalTree::soft_gap_query) and
+    /// [`tentative_gap_query()`](GapQueryIntervalTree::tentative_gap_query).
+    ///
+    /// Inserting a hard interval with
+    /// [`insert()`](GapQueryIntervalTree::insert) over a soft one
+    /// confirms it, whereas inserting a soft interval over a hard one
+    /// does nothing.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert_soft(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([5]), ii(5, 8));
+    ///
+    /// assert_eq!(
+    /// 	tree.gap_query(None, ii(0, 10)),
+    /// 	Vec::from([ii(i32::MIN, 2), ii(9, i32::MAX)])
+    /// );
+    /// assert_eq!(
+    /// 	tree.soft_gap_query(None, ii(0, 10)),
+    /// 	Vec::from([ii(i32::MIN, 4), ii(9, i32::MAX)])
+    /// );
+    /// ```
+    fn insert_soft(&mut self, identifiers: BTreeSet<D>, interval: K);
+
+    /// Gets the maximally-sized gaps that overlap the given interval
+    /// for the given identifier if one is given, treating soft
+    /// intervals as free.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert_soft(BTreeSet::from([9]), ii(7, 11));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    ///
+    /// assert_eq!(
+    /// 	tree.soft_gap_query(None, ii(9, 9)),
+    /// 	Vec::from([ii(7, 11)])
+    /// );
+    /// ```
+    fn soft_gap_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>;
+
+    /// Gets the same gaps as
+    /// [`soft_gap_query()`](GapQueryIntervalTree::soft_gap_query) but
+    /// split into the parts which are truly free and the parts which
+    /// are only tentatively free as they contain soft intervals.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapKind, GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert_soft(BTreeSet::from([9]), ii(9, 11));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    ///
+    /// assert_eq!(
+    /// 	tree.tentative_gap_query(None, ii(8, 8)),
+    /// 	Vec::from([
+    /// 		(ii(7, 8), GapKind::Free),
+    /// 		(ii(9, 11), GapKind::Tentative),
+    /// 	])
+    /// );
+    /// ```
+    fn tentative_gap_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<(K, GapKind)>
+    where
+        Q: IntervalType<I>;
+
     /// Cuts an interval from the collection for the given
     /// identifiers, if no identifiers are given all identifiers are
     /// cut.
@@ -161,6 +322,98 @@ pub trait GapQueryIntervalTree<I, K, D> {
         overlapping.pop()
     }
 
+    /// A convenience method for getting the gaps from
+    /// [`gap_query()`](GapQueryIntervalTree::gap_query) shrunk by a
+    /// buffer of `before` after the end of the preceding interval and
+    /// `after` before the start of the following interval, gaps which
+    /// become empty are dropped.
+    ///
+    /// Gaps reaching the ends of the point type have no interval on
+    /// that side and so are not shrunk on that side.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    /// tree.insert(BTreeSet::from([2]), ii(31, 40));
+    ///
+    /// assert_eq!(
+    /// 	tree.gap_query_padded(None, ii(0, 30), 2, 1),
+    /// 	Vec::from([ii(i32::MIN, 1), ii(9, 10)])
+    /// );
+    /// ```
+    fn gap_query_padded<Q>(
+        &self,
+        with_identifier: Option<D>,
+        interval: Q,
+        before: I::Distance,
+        after: I::Distance,
+    ) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+        I: PointArithmetic,
+        K: IntervalType<I>,
+    {
+        self.gap_query(with_identifier, interval)
+            .into_iter()
+            .filter_map(|gap| {
+                let start = if gap.start() == I::MIN {
+                    gap.start()
+                } else {
+                    gap.start().checked_add_distance(before)?
+                };
+                let end = if gap.end() == I::MAX {
+                    gap.end()
+                } else {
+                    gap.end().checked_sub_distance(after)?
+                };
+
+                if start <= end {
+                    Some(K::from(ii(start, end)))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Gets the result of
+    /// [`gap_query()`](GapQueryIntervalTree::gap_query) with every
+    /// identifier which has an interval overlapping the given
+    /// interval.
+    ///
+    /// ```
+    /// use std::collections::{BTreeMap, BTreeSet};
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    /// tree.insert(BTreeSet::from([2]), ii(40, 60));
+    ///
+    /// assert_eq!(
+    /// 	tree.gaps_for_all_identifiers(ii(0, 30)),
+    /// 	BTreeMap::from([
+    /// 		(5, Vec::from([ii(i32::MIN, 11), ii(29, 39)])),
+    /// 		(9, Vec::from([ii(i32::MIN, 2), ii(7, 39)])),
+    /// 	])
+    /// );
+    /// ```
+    fn gaps_for_all_identifiers<Q>(&self, interval: Q) -> BTreeMap<D, Vec<K>>
+    where
+        Q: IntervalType<I>;
+
     /// Get all identifiers which have an interval overlapping the
     /// given point.
     ///
@@ -189,4 +442,66 @@ pub trait GapQueryIntervalTree<I, K, D> {
     fn identifiers_at_point(&self, at_point: I) -> BTreeSet<D>
     where
         D: Copy;
+
+    /// Gets the number of identifiers overlapping each point of the
+    /// given interval as a step function, trimmed to the given
+    /// interval with touching steps of equal depth merged.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(5, 28));
+    /// tree.insert(BTreeSet::from([2]), ii(7, 8));
+    ///
+    /// assert_eq!(
+    /// 	tree.depth_profile(ii(0, 10)).collect::<Vec<_>>(),
+    /// 	Vec::from([
+    /// 		(ii(0, 2), 0),
+    /// 		(ii(3, 4), 1),
+    /// 		(ii(5, 8), 2),
+    /// 		(ii(9, 10), 1),
+    /// 	])
+    /// );
+    /// ```
+    fn depth_profile<Q>(&self, interval: Q) -> impl Iterator<Item = (K, usize)>
+    where
+        Q: IntervalType<I>;
+
+    /// A convenience method for getting the greatest number of
+    /// identifiers overlapping any single point in the given
+    /// interval, this is the maximum of
+    /// [`depth_profile()`](GapQueryIntervalTree::depth_profile).
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(5, 28));
+    ///
+    /// assert_eq!(tree.max_depth(ii(0, 4)), 1);
+    /// assert_eq!(tree.max_depth(ii(0, 10)), 2);
+    /// assert_eq!(tree.max_depth(ii(29, 40)), 0);
+    /// ```
+    fn max_depth<Q>(&self, interval: Q) -> usize
+    where
+        Q: IntervalType<I>,
+    {
+        self.depth_profile(interval)
+            .map(|(_, depth)| depth)
+            .max()
+            .unwrap_or(0)
+    }
 }
@@ -53,9 +53,23 @@ pub mod equality_test;
 pub mod interface;
 pub mod naive;
 pub mod no_gaps_ref;
+pub mod payload;
+pub mod placement;
+pub mod point_arithmetic;
+pub mod reservation;
+pub mod segment_map;
 
 pub use equality_test::EqualityTestGapQueryIntervalTree;
-pub use interface::GapQueryIntervalTree;
+pub use interface::{GapKind, GapQueryIntervalTree};
 pub use naive::NaiveGapQueryIntervalTree;
+pub use no_gaps_ref::BoundedGap;
 pub use no_gaps_ref::IdType;
 pub use no_gaps_ref::NoGapsRefGapQueryIntervalTree;
+pub use no_gaps_ref::TieBreak;
+pub use no_gaps_ref::UtilisationStats;
+pub use no_gaps_ref::{NoFitError, ShiftDirection};
+pub use payload::PayloadIntervalTree;
+pub use placement::{Job, PlacementError, PlacementStrategy};
+pub use point_arithmetic::PointArithmetic;
+pub use reservation::{ReservationHandle, ReservationIntervalTree};
+pub use segment_map::{Monoid, SegmentMap};
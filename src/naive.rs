@@ -20,16 +20,23 @@
 
 use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::vec::Vec;
+use core::iter::once;
 
-use nodit::interval::uu;
+use itertools::Itertools;
+use nodit::interval::{ii, uu};
 use nodit::{IntervalType, PointType};
 use nodit::NoditSet;
 
-use crate::{interface::GapQueryIntervalTree, IdType};
+use crate::{
+    interface::{GapKind, GapQueryIntervalTree},
+    IdType,
+};
 
 #[derive(Debug, Clone)]
 pub struct NaiveGapQueryIntervalTree<I, K, D> {
     pub(crate) inner: BTreeMap<D, NoditSet<I, K>>,
+    /// The subset of `inner` which is only softly present.
+    pub(crate) soft: BTreeMap<D, NoditSet<I, K>>,
 }
 
 impl<I, K, D> PartialEq for NaiveGapQueryIntervalTree<I, K, D>
@@ -39,7 +46,7 @@ where
     D: IdType,
 {
     fn eq(&self, other: &Self) -> bool {
-        self.inner == other.inner
+        self.inner == other.inner && self.soft == other.soft
     }
 }
 
@@ -58,8 +65,46 @@ where
         gaps.overlapping(interval).copied().collect()
     }
 
+    fn gap_query_trimmed<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        occupied_of(&self.inner, with_identifier)
+            .gaps_trimmed(interval)
+            .collect()
+    }
+
+    fn busy_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        let gaps = self.get_gaps(with_identifier);
+
+        gaps.gaps_untrimmed(uu())
+            .filter(|busy| busy.overlaps(&interval))
+            .collect()
+    }
+
     fn insert(&mut self, identifiers: BTreeSet<D>, interval: K) {
         for identifier in identifiers {
+            self.inner
+                .entry(identifier)
+                .or_default()
+                .insert_merge_touching_or_overlapping(interval);
+            self.cut_soft(identifier, interval);
+        }
+    }
+    fn insert_soft(&mut self, identifiers: BTreeSet<D>, interval: K) {
+        for identifier in identifiers {
+            let hard = self.hard_intervals(identifier);
+            //so as not to leave an empty set if it is all hard
+            for soft_interval in hard.gaps_trimmed(interval) {
+                self.soft
+                    .entry(identifier)
+                    .or_default()
+                    .insert_merge_touching_or_overlapping(soft_interval);
+            }
+
             self.inner
                 .entry(identifier)
                 .or_default()
@@ -75,28 +120,84 @@ where
                 for identifier in identifiers {
                     if let Some(set) = self.inner.get_mut(&identifier) {
                         let _ = set.cut(interval);
+                        if set.is_empty() {
+                            self.inner.remove(&identifier);
+                        }
                     }
+                    self.cut_soft(identifier, interval);
                 }
             }
             None => {
                 for set in self.inner.values_mut() {
                     let _ = set.cut(interval);
                 }
+                for set in self.soft.values_mut() {
+                    let _ = set.cut(interval);
+                }
+                self.inner.retain(|_, set| !set.is_empty());
+                self.soft.retain(|_, set| !set.is_empty());
             }
         }
     }
 
     fn append(&mut self, other: &mut Self) {
         for (identifier, intervals) in other.inner.extract_if(|_, _| true) {
-            if !intervals.is_empty() {
-                let store = self.inner.entry(identifier).or_default();
-                for interval in intervals {
-                    store.insert_merge_touching_or_overlapping(interval);
-                }
+            let soft = other.soft.remove(&identifier).unwrap_or_default();
+
+            for interval in without_soft(&intervals, &soft) {
+                self.insert(BTreeSet::from([identifier]), interval);
+            }
+            for interval in soft {
+                self.insert_soft(BTreeSet::from([identifier]), interval);
             }
         }
     }
 
+    fn soft_gap_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        let gaps = self.get_soft_gaps(with_identifier);
+
+        gaps.overlapping(interval).copied().collect()
+    }
+
+    fn tentative_gap_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<(K, GapKind)>
+    where
+        Q: IntervalType<I>,
+    {
+        let free_gaps = self.get_gaps(with_identifier);
+
+        //every free gap lies entirely within a soft gap
+        let mut kinds = Vec::new();
+        for soft_gap in self.get_soft_gaps(with_identifier).overlapping(interval) {
+            for free_gap in free_gaps.overlapping(*soft_gap) {
+                kinds.push((*free_gap, GapKind::Free));
+            }
+            for tentative_gap in free_gaps.gaps_trimmed(*soft_gap) {
+                kinds.push((tentative_gap, GapKind::Tentative));
+            }
+        }
+        kinds.sort_by_key(|(gap, _)| gap.start());
+
+        kinds
+    }
+
+    fn gaps_for_all_identifiers<Q>(&self, interval: Q) -> BTreeMap<D, Vec<K>>
+    where
+        Q: IntervalType<I>,
+    {
+        self.inner
+            .iter()
+            .filter(|(_, intervals)| intervals.overlaps(interval))
+            .map(|(identifier, _)| {
+                let gaps = self.get_gaps(Some(*identifier));
+
+                (*identifier, gaps.overlapping(interval).copied().collect())
+            })
+            .collect()
+    }
+
     fn identifiers_at_point(&self, at_point: I) -> BTreeSet<D> {
         self.inner
             .iter()
@@ -110,12 +211,58 @@ where
             .copied()
             .collect()
     }
+
+    fn depth_profile<Q>(&self, interval: Q) -> impl Iterator<Item = (K, usize)>
+    where
+        Q: IntervalType<I>,
+    {
+        //every point at which the depth could change
+        let mut boundaries = BTreeSet::from([interval.start()]);
+        for intervals in self.inner.values() {
+            for inner_interval in intervals.overlapping(interval) {
+                if inner_interval.start() > interval.start() {
+                    boundaries.insert(inner_interval.start());
+                }
+                if let Some(after) = inner_interval.end().up()
+                    && after <= interval.end()
+                {
+                    boundaries.insert(after);
+                }
+            }
+        }
+
+        let ends = boundaries
+            .iter()
+            .skip(1)
+            .map(|boundary| boundary.down().unwrap())
+            .chain(once(interval.end()));
+
+        boundaries
+            .iter()
+            .zip(ends)
+            .map(|(start, end)| {
+                (
+                    K::from(ii(*start, end)),
+                    self.identifiers_at_point(*start).len(),
+                )
+            })
+            .coalesce(|(x, x_depth), (y, y_depth)| {
+                if x_depth == y_depth {
+                    Ok((K::from(ii(x.start(), y.end())), x_depth))
+                } else {
+                    Err(((x, x_depth), (y, y_depth)))
+                }
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
 }
 
 impl<I, K, D> Default for NaiveGapQueryIntervalTree<I, K, D> {
     fn default() -> Self {
         Self {
             inner: BTreeMap::new(),
+            soft: BTreeMap::new(),
         }
     }
 }
@@ -133,32 +280,103 @@ where
     D: IdType,
 {
     fn get_gaps(&self, with_identifier: Option<D>) -> NoditSet<I, K> {
-        let mut total_intervals = NoditSet::new();
-        for other_identifier_intervals in
-            self.inner
-                .iter()
-                .filter_map(|(other_identifier, intervals)| {
-                    if let Some(identifier) = with_identifier.as_ref()
-                        && identifier == other_identifier
-                    {
-                        None
-                    } else {
-                        Some(intervals)
-                    }
-                })
-        {
-            for interval in other_identifier_intervals.iter() {
-                total_intervals.insert_merge_touching_or_overlapping(*interval);
+        gaps_of(&self.inner, with_identifier)
+    }
+    /// Like [`get_gaps()`](Self::get_gaps) but treating soft intervals
+    /// as free.
+    fn get_soft_gaps(&self, with_identifier: Option<D>) -> NoditSet<I, K> {
+        let hard = self
+            .inner
+            .keys()
+            .map(|identifier| (*identifier, self.hard_intervals(*identifier)))
+            .collect();
+
+        gaps_of(&hard, with_identifier)
+    }
+    /// Cuts the interval from the soft intervals of the given
+    /// identifier, removing its entry if that leaves it empty as
+    /// `into_naive()` never creates empty entries.
+    fn cut_soft<Q>(&mut self, identifier: D, interval: Q)
+    where
+        Q: IntervalType<I>,
+    {
+        if let Some(set) = self.soft.get_mut(&identifier) {
+            let _ = set.cut(interval);
+            if set.is_empty() {
+                self.soft.remove(&identifier);
             }
         }
+    }
+    fn hard_intervals(&self, identifier: D) -> NoditSet<I, K> {
+        match (self.inner.get(&identifier), self.soft.get(&identifier)) {
+            (Some(intervals), Some(soft)) => without_soft(intervals, soft),
+            (Some(intervals), None) => intervals.clone(),
+            (None, _) => NoditSet::new(),
+        }
+    }
+}
 
-        let gaps = total_intervals.gaps_untrimmed(uu());
+fn without_soft<I, K>(intervals: &NoditSet<I, K>, soft: &NoditSet<I, K>) -> NoditSet<I, K>
+where
+    I: PointType,
+    K: IntervalType<I>,
+{
+    let mut hard = intervals.clone();
+    for soft_interval in soft.iter() {
+        let _ = hard.cut(*soft_interval);
+    }
 
-        let mut set = NoditSet::new();
-        for gap in gaps {
-            set.insert_strict(gap).unwrap();
-        }
+    hard
+}
+
+fn gaps_of<I, K, D>(
+    occupancy: &BTreeMap<D, NoditSet<I, K>>,
+    with_identifier: Option<D>,
+) -> NoditSet<I, K>
+where
+    I: PointType,
+    K: IntervalType<I>,
+    D: IdType,
+{
+    let total_intervals = occupied_of(occupancy, with_identifier);
+
+    let mut set = NoditSet::new();
+    for gap in total_intervals.gaps_untrimmed(uu()) {
+        set.insert_strict(gap).unwrap();
+    }
+
+    set
+}
 
-        set
+/// The union of the intervals of every identifier other than
+/// `with_identifier`.
+fn occupied_of<I, K, D>(
+    occupancy: &BTreeMap<D, NoditSet<I, K>>,
+    with_identifier: Option<D>,
+) -> NoditSet<I, K>
+where
+    I: PointType,
+    K: IntervalType<I>,
+    D: IdType,
+{
+    let mut total_intervals = NoditSet::new();
+    for other_identifier_intervals in
+        occupancy
+            .iter()
+            .filter_map(|(other_identifier, intervals)| {
+                if let Some(identifier) = with_identifier.as_ref()
+                    && identifier == other_identifier
+                {
+                    None
+                } else {
+                    Some(intervals)
+                }
+            })
+    {
+        for interval in other_identifier_intervals.iter() {
+            total_intervals.insert_merge_touching_or_overlapping(*interval);
+        }
     }
+
+    total_intervals
 }
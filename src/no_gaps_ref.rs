@@ -18,27 +18,219 @@
    <https://www.gnu.org/licenses/>.
 */
 
-use alloc::collections::BTreeSet;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::vec::Vec;
+use core::ops::Add;
 
 use itertools::Itertools;
-use nodit::interval::{ii, iu, ui, uu};
-use nodit::{Interval, NoditMap};
-use nodit::{IntervalType, PointType};
+use nodit::interval::{ii, iu, ui};
+use nodit::{IntervalType, NoditMap, NoditSet, PointType};
 use serde::{Deserialize, Serialize};
 
-use crate::interface::GapQueryIntervalTree;
+use crate::interface::{GapKind, GapQueryIntervalTree};
 use crate::naive::NaiveGapQueryIntervalTree;
+use crate::point_arithmetic::PointArithmetic;
+use crate::segment_map::{merge_ordered, touches_ordered, SegmentMap};
 
 pub trait IdType: Eq + Ord + Copy {}
 impl<D> IdType for D where D: Eq + Ord + Copy {}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct NoGapsRefGapQueryIntervalTree<I, K, D> {
-    #[serde(bound(
+#[serde(
+    from = "SavedTree<I, K, D>",
+    into = "SavedTree<I, K, D>",
+    bound(
+        serialize = "I: PointType + Serialize, K: IntervalType<I> + Serialize, D: IdType + Serialize,",
         deserialize = "I: PointType, K: IntervalType<I> + Deserialize<'de>, D: IdType + Deserialize<'de>,"
-    ))]
-    inner: NoditMap<I, K, BTreeSet<D>>,
+    )
+)]
+pub struct NoGapsRefGapQueryIntervalTree<I, K, D> {
+    inner: SegmentMap<I, K, Segment<D>>,
+}
+
+/// The serialized form of a [`NoGapsRefGapQueryIntervalTree`].
+///
+/// The identifiers of every segment are kept in `inner` exactly as
+/// they were before soft holds and blackouts were added, which are
+/// kept in their own fields that default to empty, so trees
+/// serialized before then still deserialize.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "I: Serialize, K: Serialize, D: Serialize,",
+    deserialize = "I: PointType, K: IntervalType<I> + Deserialize<'de>, D: IdType + Deserialize<'de>,"
+))]
+struct SavedTree<I, K, D> {
+    inner: SegmentMap<I, K, BTreeSet<D>>,
+    /// The softly present identifiers of every segment with any.
+    #[serde(default = "NoditMap::new")]
+    soft: NoditMap<I, K, BTreeSet<D>>,
+    #[serde(default = "NoditSet::new")]
+    blackouts: NoditSet<I, K>,
+}
+
+impl<I, K, D> From<NoGapsRefGapQueryIntervalTree<I, K, D>> for SavedTree<I, K, D>
+where
+    I: PointType,
+    K: IntervalType<I>,
+    D: IdType,
+{
+    fn from(tree: NoGapsRefGapQueryIntervalTree<I, K, D>) -> Self {
+        let mut saved = SavedTree {
+            inner: SegmentMap::new(),
+            soft: NoditMap::new(),
+            blackouts: NoditSet::new(),
+        };
+
+        for (interval, segment) in tree.inner.into_segments() {
+            if !segment.soft.is_empty() {
+                saved
+                    .soft
+                    .insert_merge_touching_if_values_equal(interval, segment.soft)
+                    .unwrap_or_else(|_| panic!());
+            }
+            if segment.blackout {
+                saved
+                    .blackouts
+                    .insert_merge_touching(interval)
+                    .unwrap_or_else(|_| panic!());
+            }
+            //segments differing only in their soft identifiers or
+            //blackout have the same identifiers so are merged again
+            saved.inner.update(interval, |identifiers| {
+                *identifiers = segment.identifiers.clone()
+            });
+        }
+
+        saved
+    }
+}
+
+impl<I, K, D> From<SavedTree<I, K, D>> for NoGapsRefGapQueryIntervalTree<I, K, D>
+where
+    I: PointType,
+    K: IntervalType<I>,
+    D: IdType,
+{
+    fn from(saved: SavedTree<I, K, D>) -> Self {
+        let mut tree = Self::new();
+
+        for (interval, identifiers) in saved.inner.into_segments() {
+            tree.inner.update(interval, |segment| {
+                segment.identifiers = identifiers.clone()
+            });
+        }
+        for (interval, soft) in saved.soft {
+            tree.inner
+                .update(interval, |segment| segment.soft = soft.clone());
+        }
+        for interval in saved.blackouts {
+            tree.inner
+                .update(interval, |segment| segment.blackout = true);
+        }
+
+        tree
+    }
+}
+
+/// A gap returned by
+/// [`gap_query_with_horizon()`](NoGapsRefGapQueryIntervalTree::gap_query_with_horizon).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BoundedGap<K> {
+    /// The gap, which is never larger than the horizon.
+    pub gap: K,
+    /// Whether the gap continues past the start of the horizon.
+    pub truncated_left: bool,
+    /// Whether the gap continues past the end of the horizon.
+    pub truncated_right: bool,
+}
+
+/// Which of two equally near points to prefer in
+/// [`nearest_free_point()`](NoGapsRefGapQueryIntervalTree::nearest_free_point).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TieBreak {
+    /// Prefer the point before the target.
+    Earlier,
+    /// Prefer the point after the target.
+    Later,
+}
+
+/// Which way
+/// [`insert_or_shift()`](NoGapsRefGapQueryIntervalTree::insert_or_shift)
+/// shifts an interval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShiftDirection {
+    /// Shift the interval towards smaller points.
+    Earlier,
+    /// Shift the interval towards larger points.
+    Later,
+}
+
+/// The error returned by
+/// [`insert_or_shift()`](NoGapsRefGapQueryIntervalTree::insert_or_shift)
+/// when the interval does not fit anywhere within the limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NoFitError;
+
+/// The utilisation of an identifier within an interval returned by
+/// [`utilisation()`](NoGapsRefGapQueryIntervalTree::utilisation).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UtilisationStats<L> {
+    /// The total number of points the identifier is present at.
+    pub covered: L,
+    /// The number of disjoint busy intervals.
+    pub busy_intervals: usize,
+    /// The number of points in the longest busy interval.
+    pub longest_busy: L,
+}
+
+impl<L> UtilisationStats<L>
+where
+    L: Ord + Copy + From<u8> + Add<Output = L>,
+{
+    fn new() -> Self {
+        Self {
+            covered: L::from(0),
+            busy_intervals: 0,
+            longest_busy: L::from(0),
+        }
+    }
+    fn add_busy(&mut self, length: L) {
+        self.covered = self.covered + length;
+        self.busy_intervals += 1;
+        self.longest_busy = Ord::max(self.longest_busy, length);
+    }
+}
+
+/// The value of every segment in a [`NoGapsRefGapQueryIntervalTree`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Segment<D> {
+    /// Every identifier present, whether softly or not.
+    identifiers: BTreeSet<D>,
+    /// The subset of `identifiers` which are only softly present.
+    soft: BTreeSet<D>,
+    /// Whether the segment is blacked-out for every identifier.
+    blackout: bool,
+}
+
+impl<D> Segment<D>
+where
+    D: IdType,
+{
+    fn hard_identifiers(&self) -> impl Iterator<Item = &D> {
+        self.identifiers
+            .iter()
+            .filter(|identifier| !self.soft.contains(identifier))
+    }
+}
+
+impl<D> Default for Segment<D> {
+    fn default() -> Self {
+        Self {
+            identifiers: BTreeSet::new(),
+            soft: BTreeSet::new(),
+            blackout: false,
+        }
+    }
 }
 
 impl<I, K, D> GapQueryIntervalTree<I, K, D> for NoGapsRefGapQueryIntervalTree<I, K, D>
@@ -57,141 +249,332 @@ where
         }
     }
 
+    fn gap_query_trimmed<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.gaps_trimmed(with_identifier, interval).collect()
+    }
+
+    fn busy_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.inner.gap_query_by(
+            |segment| segment.blackout || !valid_identifier(with_identifier, &segment.identifiers),
+            interval,
+        )
+    }
+
     fn cut<Q>(&mut self, with_identifiers: Option<BTreeSet<D>>, interval: Q)
     where
         Q: IntervalType<I>,
     {
-        for (cut_interval, mut cut_identifiers) in self
-            .inner
-            .cut(interval)
-            //to soothe the borrow checker
-            .collect::<Vec<_>>()
-        {
-            match with_identifiers.as_ref() {
+        self.inner
+            .update(interval, |segment| match with_identifiers.as_ref() {
                 Some(identifiers) => {
-                    cut_identifiers.retain(|i| !identifiers.contains(i));
+                    segment.identifiers.retain(|i| !identifiers.contains(i));
+                    segment.soft.retain(|i| !identifiers.contains(i));
                 }
-                None => cut_identifiers.clear(),
-            }
-            self.inner
-                .insert_merge_touching_if_values_equal(cut_interval, cut_identifiers)
-                .unwrap_or_else(|_| panic!());
-        }
+                None => {
+                    segment.identifiers.clear();
+                    segment.soft.clear();
+                }
+            });
     }
 
     fn insert(&mut self, identifiers: BTreeSet<D>, interval: K) {
-        //first we extend the overlapping partial
-        //intervals with the
-        //other_specifiers and then insert them
-        //back into the data_structure with
-        //insert_merge_touching_if_values_equal to prevent
-        //fragmentation
-        //
-        //optimisation: do this without cutting and re-inserting
-        //using overlapping_mut or something
-        let cut = self
-            .inner
-            .cut(interval)
-            //to soothe the borrow checker
-            .collect::<Vec<_>>()
-            .into_iter();
-
-        let extended_cut = cut.map(|(cut_interval, mut cut_identifiers)| {
-            cut_identifiers.extend(identifiers.clone());
-            (cut_interval, cut_identifiers)
+        //a hard insertion overrides any soft presence
+        self.inner.update(interval, |segment| {
+            segment.identifiers.extend(identifiers.iter().copied());
+            segment.soft.retain(|i| !identifiers.contains(i));
         });
+    }
 
-        for (extended_interval, extended_identifiers) in extended_cut {
-            self.inner
-                .insert_merge_touching_if_values_equal(extended_interval, extended_identifiers)
-                .unwrap_or_else(|_| panic!());
-        }
+    fn insert_soft(&mut self, identifiers: BTreeSet<D>, interval: K) {
+        //a soft insertion never downgrades a hard presence
+        self.inner.update(interval, |segment| {
+            for identifier in identifiers.iter() {
+                if segment.identifiers.insert(*identifier) {
+                    segment.soft.insert(*identifier);
+                }
+            }
+        });
     }
 
     fn append(&mut self, other: &mut Self) {
-        for (interval, identifiers) in other.inner.remove_overlapping(uu()) {
-            self.insert(identifiers, interval);
+        for (interval, segment) in core::mem::take(&mut other.inner).into_segments() {
+            self.insert(segment.hard_identifiers().copied().collect(), interval);
+            self.insert_soft(segment.soft, interval);
+            if segment.blackout {
+                self.insert_blackout(interval);
+            }
         }
     }
 
-    fn identifiers_at_point(&self, at_point: I) -> BTreeSet<D> {
-        self.inner
-            .get_at_point(at_point)
-            .cloned()
-            .unwrap_or(BTreeSet::new())
-    }
-}
-
-impl<I, K, D> NoGapsRefGapQueryIntervalTree<I, K, D>
-where
-    I: PointType,
-    K: IntervalType<I>,
-    D: IdType,
-{
-    fn get_gaps_with_identifier<Q>(&self, identifier: D, interval: Q) -> Vec<K>
+    fn gaps_for_all_identifiers<Q>(&self, interval: Q) -> BTreeMap<D, Vec<K>>
     where
         Q: IntervalType<I>,
     {
-        let valid_gaps = self
+        //whom a segment is free for
+        #[derive(Clone, Copy, PartialEq)]
+        enum FreeFor<D> {
+            Everyone,
+            Only(D),
+            NoOne,
+        }
+        let free_for = |segment: &Segment<D>| {
+            if segment.blackout || segment.identifiers.len() > 1 {
+                FreeFor::NoOne
+            } else {
+                match segment.identifiers.first() {
+                    Some(identifier) => FreeFor::Only(*identifier),
+                    None => FreeFor::Everyone,
+                }
+            }
+        };
+        //outside the interval we only need to walk until a segment
+        //which, along with the ones before it, ends the gaps of every
+        //identifier, which is a segment free for no-one or the second
+        //different sole identifier
+        let ends_all = |sole: &mut Option<D>, free: FreeFor<D>| match free {
+            FreeFor::Everyone => false,
+            FreeFor::Only(identifier) => match sole {
+                Some(sole) => *sole != identifier,
+                None => {
+                    *sole = Some(identifier);
+                    false
+                }
+            },
+            FreeFor::NoOne => true,
+        };
+
+        //since there are no gaps there is always a first segment
+        let (first, _) = self.inner.overlapping(interval).next().unwrap();
+
+        let mut left = Vec::new();
+        if let Some(before) = first.start().down() {
+            let mut sole = None;
+            for (segment, value) in self.inner.overlapping(ui(before)).rev() {
+                left.push((segment, value, false));
+                if ends_all(&mut sole, free_for(value)) {
+                    break;
+                }
+            }
+            left.reverse();
+        }
+        let middle = self
             .inner
             .overlapping(interval)
-            .filter_map(move |(inner_interval, other_identifiers)| {
-                if valid_identifier(Some(identifier), other_identifiers) {
-                    Some(inner_interval)
-                } else {
-                    None
-                }
+            .map(|(segment, value)| (segment, value, true));
+        let mut right_sole = None;
+        let mut right_ended = false;
+        let right = self
+            .inner
+            .overlapping(interval)
+            .next_back()
+            .and_then(|(last, _)| last.end().up())
+            .into_iter()
+            .flat_map(|after| self.inner.overlapping(iu(after)))
+            .take_while(move |(_, value)| {
+                let take = !right_ended;
+                right_ended = right_ended || ends_all(&mut right_sole, free_for(value));
+                take
             })
-            .copied();
-        //we don't want end ones as they are
-        //handled separately
-        let non_end_gaps = valid_gaps
-            .filter(|gap| !gap.contains(interval.start()) && !gap.contains(interval.end()));
-
-        //instead of using possibly-partial end gaps we will
-        //replace them with completely_iterated gaps
-        //expanded on both sides outwardly only not inwardly
-        let mut left_gap = self.expand_gaps_at_point_left(identifier, interval.start());
-        let mut right_gap = self.expand_gaps_at_point_right(identifier, interval.end());
-        //if they refer to the save gap then merge them
-        if let Some(left) = left_gap.as_mut()
-            && let Some(right) = right_gap
-            && overlaps_ordered(*left, right)
-        {
-            *left = K::from(merge_ordered(*left, right));
-            right_gap = None;
+            .map(|(segment, value)| (segment, value, false));
+
+        let overlaps = |gap: &K| gap.start() <= interval.end() && gap.end() >= interval.start();
+
+        //a single pass in which at most one identifier, the sole one,
+        //has a gap continuing through segments free only for it,
+        //every other identifier shares the gap made of the run of
+        //segments free for everyone up to the current segment
+        let mut identifiers = BTreeSet::new();
+        let mut all: BTreeMap<D, Vec<K>> = BTreeMap::new();
+        //the closed shared gaps along with up to two identifiers they
+        //are not shared with, for identifiers seen after they closed
+        let mut shared: Vec<(K, [Option<D>; 2])> = Vec::new();
+        let mut sole: Option<(D, I)> = None;
+        let mut everyone_start: Option<I> = None;
+        let mut last_end = None;
+
+        let close_shared = |all: &mut BTreeMap<D, Vec<K>>,
+                            shared: &mut Vec<(K, [Option<D>; 2])>,
+                            gap: K,
+                            except: [Option<D>; 2]| {
+            if overlaps(&gap) {
+                for (identifier, gaps) in all.iter_mut() {
+                    if !except.contains(&Some(*identifier)) {
+                        gaps.push(gap);
+                    }
+                }
+                shared.push((gap, except));
+            }
+        };
+        let close_sole = |all: &mut BTreeMap<D, Vec<K>>, identifier: D, gap: K| {
+            if overlaps(&gap) {
+                all.get_mut(&identifier).unwrap().push(gap);
+            }
+        };
+
+        for (segment, value, in_interval) in left.into_iter().chain(middle).chain(right) {
+            match free_for(value) {
+                FreeFor::Everyone => {
+                    everyone_start.get_or_insert(segment.start());
+                }
+                FreeFor::Only(identifier) => {
+                    let sole_identifier = sole.map(|(sole_identifier, _)| sole_identifier);
+                    if let Some((sole_identifier, sole_start)) = sole
+                        && sole_identifier != identifier
+                    {
+                        let end = segment.start().down().unwrap();
+                        close_sole(&mut all, sole_identifier, K::from(ii(sole_start, end)));
+                    }
+                    if let Some(start) = everyone_start {
+                        let end = segment.start().down().unwrap();
+                        close_shared(
+                            &mut all,
+                            &mut shared,
+                            K::from(ii(start, end)),
+                            [sole_identifier, Some(identifier)],
+                        );
+                    }
+
+                    let start = match sole {
+                        Some((sole_identifier, sole_start)) if sole_identifier == identifier => {
+                            sole_start
+                        }
+                        _ => everyone_start.unwrap_or(segment.start()),
+                    };
+                    sole = Some((identifier, start));
+                    everyone_start = None;
+                }
+                FreeFor::NoOne => {
+                    if let Some((sole_identifier, sole_start)) = sole {
+                        let end = segment.start().down().unwrap();
+                        close_sole(&mut all, sole_identifier, K::from(ii(sole_start, end)));
+                    }
+                    if let Some(start) = everyone_start {
+                        let end = segment.start().down().unwrap();
+                        close_shared(
+                            &mut all,
+                            &mut shared,
+                            K::from(ii(start, end)),
+                            [sole.map(|(sole_identifier, _)| sole_identifier), None],
+                        );
+                    }
+
+                    sole = None;
+                    everyone_start = None;
+                }
+            }
+
+            for identifier in value.identifiers.iter() {
+                all.entry(*identifier).or_insert_with(|| {
+                    shared
+                        .iter()
+                        .filter(|(_, except)| !except.contains(&Some(*identifier)))
+                        .map(|(gap, _)| *gap)
+                        .collect()
+                });
+            }
+            if in_interval {
+                identifiers.extend(value.identifiers.iter().copied());
+            }
+            last_end = Some(segment.end());
+        }
+
+        //the gaps still open at the end of the pass
+        let last_end = last_end.unwrap();
+        if let Some((sole_identifier, sole_start)) = sole {
+            close_sole(&mut all, sole_identifier, K::from(ii(sole_start, last_end)));
         }
+        if let Some(start) = everyone_start {
+            close_shared(
+                &mut all,
+                &mut shared,
+                K::from(ii(start, last_end)),
+                [sole.map(|(sole_identifier, _)| sole_identifier), None],
+            );
+        }
+
+        all.retain(|identifier, _| identifiers.contains(identifier));
 
-        //then we need to chain these iterators together and
-        //progressively merge touching gaps
-        let all_non_merged_gaps = left_gap.into_iter().chain(non_end_gaps).chain(right_gap);
+        all
+    }
 
-        //the final proper merged result
-        all_non_merged_gaps
-            .coalesce(|x, y| {
-                if touches_ordered(x, y) {
-                    Ok(K::from(merge_ordered(x, y)))
+    fn identifiers_at_point(&self, at_point: I) -> BTreeSet<D> {
+        self.inner.get_at_point(at_point).identifiers.clone()
+    }
+
+    fn soft_gap_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.inner.gap_query_by(
+            |segment| {
+                !segment.blackout
+                    && segment
+                        .hard_identifiers()
+                        .all(|identifier| Some(*identifier) == with_identifier)
+            },
+            interval,
+        )
+    }
+
+    fn tentative_gap_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<(K, GapKind)>
+    where
+        Q: IntervalType<I>,
+    {
+        //every soft gap is made up of whole segments so we can
+        //classify them and merge touching segments of the same kind
+        self.soft_gap_query(with_identifier, interval)
+            .into_iter()
+            .flat_map(|gap| {
+                self.inner
+                    .overlapping(gap)
+                    .map(|(inner_interval, segment)| {
+                        let kind = if valid_identifier(with_identifier, &segment.identifiers) {
+                            GapKind::Free
+                        } else {
+                            GapKind::Tentative
+                        };
+                        (*inner_interval, kind)
+                    })
+            })
+            .coalesce(|(x, x_kind), (y, y_kind)| {
+                if x_kind == y_kind && touches_ordered(x, y) {
+                    Ok((K::from(merge_ordered(x, y)), x_kind))
                 } else {
-                    Err((x, y))
+                    Err(((x, x_kind), (y, y_kind)))
                 }
             })
             .collect()
     }
-    fn get_gaps_no_identifier<Q>(&self, interval: Q) -> Vec<K>
+
+    fn depth_profile<Q>(&self, interval: Q) -> impl Iterator<Item = (K, usize)>
     where
         Q: IntervalType<I>,
     {
         self.inner
             .overlapping(interval)
-            .filter_map(|(inner_interval, other_specifiers)| {
-                if other_specifiers.is_empty() {
-                    Some(inner_interval)
+            .map(move |(inner_interval, segment)| {
+                let trimmed = ii(
+                    I::max(inner_interval.start(), interval.start()),
+                    I::min(inner_interval.end(), interval.end()),
+                );
+                (K::from(trimmed), segment.identifiers.len())
+            })
+            //different identifiers can have the same depth
+            .coalesce(|(x, x_depth), (y, y_depth)| {
+                if x_depth == y_depth {
+                    //since there are no gaps we know they will always
+                    //touch
+                    Ok((K::from(merge_ordered(x, y)), x_depth))
                 } else {
-                    None
+                    Err(((x, x_depth), (y, y_depth)))
                 }
             })
-            .copied()
-            .collect()
     }
 }
 
@@ -199,48 +582,1131 @@ impl<I, K, D> NoGapsRefGapQueryIntervalTree<I, K, D>
 where
     I: PointType,
     K: IntervalType<I>,
-    D: Eq + Ord + Copy,
+    D: IdType,
 {
-    fn expand_gaps_at_point_right(&self, identifier: D, point: I) -> Option<K> {
-        let overlapping_right = self.inner.overlapping(iu(point));
+    /// Gets the maximally-sized intervals that overlap the given
+    /// interval in which at least `k` identifiers overlap every
+    /// point.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(5, 28));
+    /// tree.insert(BTreeSet::from([2]), ii(7, 8));
+    ///
+    /// assert_eq!(
+    /// 	tree.covered_at_least(2, ii(0, 10)),
+    /// 	Vec::from([ii(5, 8)])
+    /// );
+    /// assert_eq!(tree.covered_at_least(3, ii(0, 10)), Vec::from([]));
+    /// ```
+    pub fn covered_at_least<Q>(&self, k: usize, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.segments_matching(|identifiers| identifiers.len() >= k, interval)
+    }
 
-        overlapping_right
-            .take_while(|(_, other_identifiers)| {
-                valid_identifier(Some(identifier), other_identifiers)
-            })
-            .map(|(x, _)| *x)
-            .coalesce(|x, y| {
-                //since there are no gaps we know they will always
-                //touch
-                Ok(K::from(merge_ordered(x, y)))
+    /// Gets the maximally-sized intervals that overlap the given
+    /// interval in which none of the given identifiers are present,
+    /// regardless of any other identifiers.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    /// tree.insert(BTreeSet::from([2]), ii(7, 10));
+    ///
+    /// assert_eq!(
+    /// 	tree.common_free(&BTreeSet::from([5, 9]), ii(0, 20)),
+    /// 	Vec::from([ii(i32::MIN, 2), ii(7, 11)])
+    /// );
+    /// ```
+    pub fn common_free<Q>(&self, identifiers: &BTreeSet<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.gap_query_by(
+            |other_identifiers| other_identifiers.is_disjoint(identifiers),
+            interval,
+        )
+    }
+
+    /// Gets the maximally-sized intervals that overlap the given
+    /// interval in which all of the given identifiers are present at
+    /// once.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 16));
+    /// tree.insert(BTreeSet::from([9]), ii(5, 8));
+    /// tree.insert(BTreeSet::from([9, 2]), ii(9, 12));
+    ///
+    /// assert_eq!(
+    /// 	tree.all_busy(&BTreeSet::from([5, 9]), ii(0, 20)),
+    /// 	Vec::from([ii(5, 12)])
+    /// );
+    /// ```
+    pub fn all_busy<Q>(&self, identifiers: &BTreeSet<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.segments_matching(
+            |other_identifiers| other_identifiers.is_superset(identifiers),
+            interval,
+        )
+    }
+
+    /// Gets the maximally-sized intervals that overlap the given
+    /// interval in which exactly the given identifiers are present.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 16));
+    /// tree.insert(BTreeSet::from([9]), ii(5, 8));
+    /// tree.insert(BTreeSet::from([9, 2]), ii(9, 12));
+    ///
+    /// assert_eq!(
+    /// 	tree.segments_with_exact(&BTreeSet::from([5, 9]), ii(0, 20)),
+    /// 	Vec::from([ii(5, 8)])
+    /// );
+    /// ```
+    pub fn segments_with_exact<Q>(&self, identifiers: &BTreeSet<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.segments_matching(
+            |other_identifiers| other_identifiers == identifiers,
+            interval,
+        )
+    }
+
+    /// Gets the maximally-sized intervals that overlap the given
+    /// interval in which the set of identifiers present satisfies
+    /// `predicate`.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 16));
+    /// tree.insert(BTreeSet::from([9]), ii(5, 8));
+    /// tree.insert(BTreeSet::from([9, 2]), ii(9, 12));
+    ///
+    /// assert_eq!(
+    /// 	tree.segments_matching(|identifiers| identifiers.contains(&9), ii(0, 20)),
+    /// 	Vec::from([ii(5, 12)])
+    /// );
+    /// ```
+    pub fn segments_matching<Q, P>(&self, predicate: P, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+        P: Fn(&BTreeSet<D>) -> bool,
+    {
+        self.inner
+            .gap_query_by(|segment| predicate(&segment.identifiers), interval)
+    }
+
+    /// Blacks-out an interval for every identifier, no gap-query will
+    /// return a gap overlapping a blacked-out interval, even to an
+    /// identifier which is present there.
+    ///
+    /// Blacked-out intervals are not identifiers so they don't affect
+    /// [`identifiers_at_point()`](GapQueryIntervalTree::identifiers_at_point).
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert_blackout(ii(5, 8));
+    ///
+    /// assert_eq!(
+    /// 	tree.gap_query(Some(5), ii(0, 10)),
+    /// 	Vec::from([ii(i32::MIN, 4), ii(9, i32::MAX)])
+    /// );
+    /// assert_eq!(tree.identifiers_at_point(7), BTreeSet::from([]));
+    /// ```
+    pub fn insert_blackout(&mut self, interval: K) {
+        self.inner
+            .update(interval, |segment| segment.blackout = true);
+    }
+
+    /// Cuts an interval from the blacked-out intervals.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::<_, _, u8>::new();
+    /// tree.insert_blackout(ii(3, 8));
+    /// tree.cut_blackout(ii(5, 6));
+    ///
+    /// assert_eq!(
+    /// 	tree.gap_query(None, ii(4, 7)),
+    /// 	Vec::from([ii(5, 6)])
+    /// );
+    /// ```
+    pub fn cut_blackout<Q>(&mut self, interval: Q)
+    where
+        Q: IntervalType<I>,
+    {
+        self.inner
+            .update(interval, |segment| segment.blackout = false);
+    }
+
+    /// Gets the maximally-sized gaps that overlap the given interval
+    /// where a point is free if the set of identifiers present
+    /// satisfies `predicate`.
+    ///
+    /// [`gap_query()`](GapQueryIntervalTree::gap_query) is this with
+    /// a predicate which accepts only the empty set and the set
+    /// containing just the given identifier.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([100]), ii(5, 10));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    ///
+    /// //identifiers of 100 or more are only low-priority holders
+    /// assert_eq!(
+    /// 	tree.gap_query_by(
+    /// 		|identifiers| identifiers.iter().all(|x| *x >= 100),
+    /// 		ii(8, 8)
+    /// 	),
+    /// 	Vec::from([ii(7, 11)])
+    /// );
+    /// ```
+    pub fn gap_query_by<Q, P>(&self, predicate: P, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+        P: Fn(&BTreeSet<D>) -> bool,
+    {
+        self.gaps(&predicate, interval).collect()
+    }
+
+    /// The lazy version of
+    /// [`gap_query_trimmed()`](GapQueryIntervalTree::gap_query_trimmed).
+    pub(crate) fn gaps_trimmed<'a, Q>(
+        &'a self,
+        with_identifier: Option<D>,
+        interval: Q,
+    ) -> impl Iterator<Item = K> + 'a
+    where
+        Q: IntervalType<I> + 'a,
+    {
+        self.inner.gaps_trimmed(
+            move |segment: &Segment<D>| {
+                !segment.blackout && valid_identifier(with_identifier, &segment.identifiers)
+            },
+            interval,
+        )
+    }
+
+    /// Like [`gaps_trimmed()`](NoGapsRefGapQueryIntervalTree::gaps_trimmed)
+    /// but for the intervals in which the given identifier is not
+    /// present, regardless of any other identifiers, like
+    /// [`common_free()`](NoGapsRefGapQueryIntervalTree::common_free)
+    /// of just that identifier.
+    pub(crate) fn free_time_trimmed<'a, Q>(
+        &'a self,
+        identifier: D,
+        interval: Q,
+    ) -> impl Iterator<Item = K> + 'a
+    where
+        Q: IntervalType<I> + 'a,
+    {
+        self.inner.gaps_trimmed(
+            move |segment: &Segment<D>| {
+                !segment.blackout && !segment.identifiers.contains(&identifier)
+            },
+            interval,
+        )
+    }
+
+    /// The lazy version of
+    /// [`gap_query_by()`](NoGapsRefGapQueryIntervalTree::gap_query_by).
+    fn gaps<'a, Q, P>(&'a self, predicate: P, interval: Q) -> impl Iterator<Item = K> + 'a
+    where
+        Q: IntervalType<I> + 'a,
+        P: Fn(&BTreeSet<D>) -> bool + Clone + 'a,
+    {
+        self.inner.gaps(
+            move |segment: &Segment<D>| !segment.blackout && predicate(&segment.identifiers),
+            interval,
+        )
+    }
+
+    /// Like [`gap_query()`](GapQueryIntervalTree::gap_query) but the
+    /// gaps are not expanded past the given horizon, which bounds the
+    /// number of segments traversed.
+    ///
+    /// The gaps are those from
+    /// [`gap_query()`](GapQueryIntervalTree::gap_query) intersected
+    /// with the horizon, each marked with whether it was truncated by
+    /// the horizon or is truly maximal on each side.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	BoundedGap, GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    ///
+    /// assert_eq!(
+    /// 	tree.gap_query_with_horizon(None, ii(8, 30), ii(0, 40)),
+    /// 	Vec::from([
+    /// 		BoundedGap {
+    /// 			gap: ii(7, 11),
+    /// 			truncated_left: false,
+    /// 			truncated_right: false,
+    /// 		},
+    /// 		BoundedGap {
+    /// 			gap: ii(29, 40),
+    /// 			truncated_left: false,
+    /// 			truncated_right: true,
+    /// 		},
+    /// 	])
+    /// );
+    /// ```
+    pub fn gap_query_with_horizon<Q, H>(
+        &self,
+        with_identifier: Option<D>,
+        interval: Q,
+        horizon: H,
+    ) -> Vec<BoundedGap<K>>
+    where
+        Q: IntervalType<I>,
+        H: IntervalType<I>,
+    {
+        self.inner
+            .gaps_within(
+                |segment: &Segment<D>| {
+                    !segment.blackout && valid_identifier(with_identifier, &segment.identifiers)
+                },
+                interval,
+                horizon,
+            )
+            .map(|(gap, truncated_left, truncated_right)| BoundedGap {
+                gap,
+                truncated_left,
+                truncated_right,
             })
-            .next()
+            .collect()
     }
-    fn expand_gaps_at_point_left(&self, identifier: D, point: I) -> Option<K> {
+
+    /// Gets the same result as calling
+    /// [`gap_query_at_point()`](GapQueryIntervalTree::gap_query_at_point)
+    /// for each of the given points but in a single walk over the
+    /// tree, reusing each gap for every point it contains.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the points are not in ascending order.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    ///
+    /// assert_eq!(
+    /// 	tree.gap_query_at_points(None, [2, 5, 7, 11, 30])
+    /// 		.collect::<Vec<_>>(),
+    /// 	Vec::from([
+    /// 		Some(ii(i32::MIN, 2)),
+    /// 		None,
+    /// 		Some(ii(7, 11)),
+    /// 		Some(ii(7, 11)),
+    /// 		Some(ii(29, i32::MAX)),
+    /// 	])
+    /// );
+    /// ```
+    pub fn gap_query_at_points<'a, T>(
+        &'a self,
+        with_identifier: Option<D>,
+        points: T,
+    ) -> impl Iterator<Item = Option<K>> + 'a
+    where
+        T: IntoIterator<Item = I>,
+        T::IntoIter: 'a,
+        D: 'a,
+    {
+        self.inner.gaps_at_points(
+            move |segment: &Segment<D>| {
+                !segment.blackout && valid_identifier(with_identifier, &segment.identifiers)
+            },
+            points,
+        )
+    }
+
+    /// Gets the nearest point to `target` which lies in a gap from
+    /// [`gap_query()`](GapQueryIntervalTree::gap_query), or `None` if
+    /// there are no gaps.
+    ///
+    /// The search walks outwards in both directions from the segment
+    /// containing `target` at once, so it only traverses the segments
+    /// nearer than the answer, plus at most one on each side.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree, TieBreak,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 7));
+    /// tree.insert(BTreeSet::from([9]), ii(8, 28));
+    ///
+    /// assert_eq!(tree.nearest_free_point(None, 1, TieBreak::Earlier), Some(1));
+    /// assert_eq!(tree.nearest_free_point(None, 20, TieBreak::Earlier), Some(29));
+    ///
+    /// //2 and 8 are equally near to 5
+    /// assert_eq!(tree.nearest_free_point(Some(9), 5, TieBreak::Earlier), Some(2));
+    /// assert_eq!(tree.nearest_free_point(Some(9), 5, TieBreak::Later), Some(8));
+    /// ```
+    pub fn nearest_free_point(
+        &self,
+        with_identifier: Option<D>,
+        target: I,
+        tie_break: TieBreak,
+    ) -> Option<I>
+    where
+        I: PointArithmetic,
+    {
+        let free = |segment: &Segment<D>| {
+            !segment.blackout && valid_identifier(with_identifier, &segment.identifiers)
+        };
+
+        if free(self.inner.get_at_point(target)) {
+            return Some(target);
+        }
+
+        //the segment containing target, which is not free
+        let (segment, _) = self.inner.overlapping(ii(target, target)).next().unwrap();
+
         //we are going in reverse since we are going left
-        let overlapping_left = self.inner.overlapping(ui(point)).rev();
+        //
+        //since there are no gaps we know they will always touch so
+        //the nearest point of a segment is the one closest to target
+        let mut left = segment
+            .start()
+            .down()
+            .into_iter()
+            .flat_map(|before| self.inner.overlapping(ui(before)).rev())
+            .map(|(segment, value)| (segment.end(), free(value)))
+            .peekable();
+        let mut right = segment
+            .end()
+            .up()
+            .into_iter()
+            .flat_map(|after| self.inner.overlapping(iu(after)))
+            .map(|(segment, value)| (segment.start(), free(value)))
+            .peekable();
+
+        loop {
+            match (left.peek().copied(), right.peek().copied()) {
+                (None, None) => return None,
+                (Some((point, is_free)), None) | (None, Some((point, is_free))) => {
+                    if is_free {
+                        return Some(point);
+                    }
+                    left.next();
+                    right.next();
+                }
+                (Some((left_point, left_free)), Some((right_point, right_free))) => {
+                    let left_distance = target.distance(left_point);
+                    let right_distance = target.distance(right_point);
+
+                    if left_distance < right_distance {
+                        if left_free {
+                            return Some(left_point);
+                        }
+                        left.next();
+                    } else if right_distance < left_distance {
+                        if right_free {
+                            return Some(right_point);
+                        }
+                        right.next();
+                    } else {
+                        match (left_free, right_free, tie_break) {
+                            (true, true, TieBreak::Earlier) | (true, false, _) => {
+                                return Some(left_point);
+                            }
+                            (true, true, TieBreak::Later) | (false, true, _) => {
+                                return Some(right_point);
+                            }
+                            (false, false, _) => {
+                                left.next();
+                                right.next();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gets the `k` gaps from
+    /// [`gap_query()`](GapQueryIntervalTree::gap_query) nearest to
+    /// `target` with at least `min_len` points, in order of their
+    /// distance from `target`.
+    ///
+    /// A gap containing `target` has a distance of zero and equally
+    /// near gaps are ordered earliest first.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(9, 20));
+    /// tree.insert(BTreeSet::from([2]), ii(22, 40));
+    ///
+    /// assert_eq!(
+    /// 	tree.nearest_gaps(None, 15, 3, 1_u32).collect::<Vec<_>>(),
+    /// 	Vec::from([ii(21, 21), ii(7, 8), ii(i32::MIN, 2)])
+    /// );
+    /// assert_eq!(
+    /// 	tree.nearest_gaps(None, 15, 3, 2).collect::<Vec<_>>(),
+    /// 	Vec::from([ii(7, 8), ii(i32::MIN, 2), ii(41, i32::MAX)])
+    /// );
+    /// ```
+    pub fn nearest_gaps(
+        &self,
+        with_identifier: Option<D>,
+        target: I,
+        k: usize,
+        min_len: I::Distance,
+    ) -> impl Iterator<Item = K> + '_
+    where
+        I: PointArithmetic,
+    {
+        let free = move |segment: &Segment<D>| {
+            !segment.blackout && valid_identifier(with_identifier, &segment.identifiers)
+        };
+        let long_enough = move |gap: &K| I::length(gap.start(), gap.end()) >= min_len;
+
+        //the gap containing target, if there is one, which the walks
+        //start either side of
+        let central = self
+            .gaps(
+                move |other_identifiers| valid_identifier(with_identifier, other_identifiers),
+                ii(target, target),
+            )
+            .next();
+        let (before, after) = match central {
+            Some(gap) => (gap.start().down(), gap.end().up()),
+            None => {
+                let (segment, _) = self.inner.overlapping(ii(target, target)).next().unwrap();
+                (segment.start().down(), segment.end().up())
+            }
+        };
+
+        let left = before
+            .into_iter()
+            .flat_map(move |before| self.inner.gaps_trimmed_rev(free, ui(before)))
+            .filter(long_enough)
+            .map(move |gap| (target.distance(gap.end()), gap));
+        let right = after
+            .into_iter()
+            .flat_map(move |after| self.inner.gaps_trimmed(free, iu(after)))
+            .filter(long_enough)
+            .map(move |gap| (gap.start().distance(target), gap));
+
+        central
+            .filter(long_enough)
+            .into_iter()
+            .chain(
+                //ties take from the left first as it is earlier
+                left.merge_by(right, |(x_distance, _), (y_distance, _)| {
+                    x_distance <= y_distance
+                })
+                .map(|(_, gap)| gap),
+            )
+            .take(k)
+    }
+
+    /// Inserts an interval for the given identifiers shifted by the
+    /// smallest amount, up to `limit`, in the given direction so that
+    /// it does not overlap any other identifiers or blackouts,
+    /// returning the interval actually inserted.
+    ///
+    /// The interval keeps its length and nothing is inserted if it
+    /// does not fit anywhere within the limit.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoFitError, NoGapsRefGapQueryIntervalTree,
+    /// 	ShiftDirection,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(10, 28));
+    ///
+    /// assert_eq!(
+    /// 	tree.insert_or_shift(
+    /// 		BTreeSet::from([9]),
+    /// 		ii(4, 7),
+    /// 		ShiftDirection::Later,
+    /// 		10_u32
+    /// 	),
+    /// 	Ok(ii(7, 10))
+    /// );
+    /// assert_eq!(
+    /// 	tree.insert_or_shift(
+    /// 		BTreeSet::from([2]),
+    /// 		ii(4, 7),
+    /// 		ShiftDirection::Later,
+    /// 		10_u32
+    /// 	),
+    /// 	Err(NoFitError)
+    /// );
+    /// assert_eq!(
+    /// 	tree.insert_or_shift(
+    /// 		BTreeSet::from([2]),
+    /// 		ii(4, 7),
+    /// 		ShiftDirection::Earlier,
+    /// 		10_u32
+    /// 	),
+    /// 	Ok(ii(-1, 2))
+    /// );
+    ///
+    /// //an interval covering every point cannot be shifted
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([3]), ii(-18_i8, 127));
+    /// assert_eq!(
+    /// 	tree.insert_or_shift(
+    /// 		BTreeSet::from([0]),
+    /// 		ii(i8::MIN, i8::MAX),
+    /// 		ShiftDirection::Later,
+    /// 		1_u8
+    /// 	),
+    /// 	Err(NoFitError)
+    /// );
+    /// assert_eq!(
+    /// 	tree.insert_or_shift(
+    /// 		BTreeSet::from([0, 3]),
+    /// 		ii(i8::MIN, i8::MAX),
+    /// 		ShiftDirection::Later,
+    /// 		1_u8
+    /// 	),
+    /// 	Ok(ii(i8::MIN, i8::MAX))
+    /// );
+    /// ```
+    pub fn insert_or_shift(
+        &mut self,
+        identifiers: BTreeSet<D>,
+        interval: K,
+        direction: ShiftDirection,
+        limit: I::Distance,
+    ) -> Result<K, NoFitError>
+    where
+        I: PointArithmetic,
+    {
+        let length = I::length(interval.start(), interval.end());
+        let one = I::Distance::from(1);
+
+        let fits =
+            |segment: &Segment<D>| !segment.blackout && segment.identifiers.is_subset(&identifiers);
+
+        //the length of an interval covering every point saturates, but
+        //it has nowhere to shift to anyway
+        if interval.start() == I::MIN && interval.end() == I::MAX {
+            if !self
+                .inner
+                .overlapping(interval)
+                .all(|(_, value)| fits(value))
+            {
+                return Err(NoFitError);
+            }
+            self.insert(identifiers, interval);
+
+            return Ok(interval);
+        }
+
+        //every position within the limit lies in the window so the
+        //first long enough free interval in the window in the given
+        //direction is the smallest shift
+        let shifted = match direction {
+            ShiftDirection::Later => {
+                let window_end = interval.end().checked_add_distance(limit).unwrap_or(I::MAX);
+
+                self.inner
+                    .gaps_trimmed(fits, ii(interval.start(), window_end))
+                    .find(|free| I::length(free.start(), free.end()) >= length)
+                    .map(|free| {
+                        let end = free.start().checked_add_distance(length - one).unwrap();
+                        K::from(ii(free.start(), end))
+                    })
+            }
+            ShiftDirection::Earlier => {
+                let window_start = interval
+                    .start()
+                    .checked_sub_distance(limit)
+                    .unwrap_or(I::MIN);
+                let window = ii(window_start, interval.end());
+
+                self.inner
+                    .gaps_trimmed_rev(fits, window)
+                    .find(|free| I::length(free.start(), free.end()) >= length)
+                    .map(|free| {
+                        let start = free.end().checked_sub_distance(length - one).unwrap();
+                        K::from(ii(start, free.end()))
+                    })
+            }
+        };
+
+        let shifted = shifted.ok_or(NoFitError)?;
+        self.insert(identifiers, shifted);
+
+        Ok(shifted)
+    }
 
-        overlapping_left
-            .take_while(|(_, other_identifiers)| {
-                valid_identifier(Some(identifier), other_identifiers)
+    /// Gets the largest gap from
+    /// [`gap_query_trimmed()`](GapQueryIntervalTree::gap_query_trimmed),
+    /// the earliest if there are multiple, or `None` if there are no
+    /// gaps.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    ///
+    /// assert_eq!(tree.largest_gap(None, ii(0, 30)), Some(ii(7, 11)));
+    /// assert_eq!(tree.largest_gap(None, ii(4, 6)), None);
+    /// ```
+    pub fn largest_gap<Q>(&self, with_identifier: Option<D>, interval: Q) -> Option<K>
+    where
+        Q: IntervalType<I>,
+        I: PointArithmetic,
+    {
+        self.gaps_trimmed(with_identifier, interval).max_by(|x, y| {
+            I::length(x.start(), x.end())
+                .cmp(&I::length(y.start(), y.end()))
+                //prefer the earlier gap on ties
+                .then(y.start().cmp(&x.start()))
+        })
+    }
+
+    /// Gets the total number of points in the gaps from
+    /// [`gap_query_trimmed()`](GapQueryIntervalTree::gap_query_trimmed).
+    ///
+    /// This saturates at the largest distance if every point is free.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    ///
+    /// //0..=2, 7..=11 and 29..=30
+    /// assert_eq!(tree.free_measure(None, ii(0, 30)), 3 + 5 + 2_u32);
+    /// assert_eq!(tree.free_measure(Some(9), ii(0, 30)), 3 + 24_u32);
+    /// ```
+    pub fn free_measure<Q>(&self, with_identifier: Option<D>, interval: Q) -> I::Distance
+    where
+        Q: IntervalType<I>,
+        I: PointArithmetic,
+    {
+        //the gaps are disjoint so the sum can only overflow if there
+        //is a single gap covering every point, which saturates
+        self.gaps_trimmed(with_identifier, interval)
+            .fold(I::Distance::from(0), |total, gap| {
+                total + I::length(gap.start(), gap.end())
             })
-            .map(|(x, _)| *x)
-            .coalesce(|x, y| {
-                //since we are going from right to left these will
-                //be reversed too
-                //
-                //since there are no gaps we know they will always
-                //touch
-                Ok(K::from(merge_ordered(y, x)))
+    }
+
+    /// Gets the utilisation of the given identifier within the given
+    /// interval, busy intervals are trimmed to the interval and
+    /// include soft intervals.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// 	UtilisationStats,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([5, 9]), ii(12, 28));
+    ///
+    /// assert_eq!(
+    /// 	tree.utilisation(5, ii(0, 20)),
+    /// 	UtilisationStats {
+    /// 		covered: 4 + 9,
+    /// 		busy_intervals: 2,
+    /// 		longest_busy: 9_u32,
+    /// 	}
+    /// );
+    /// ```
+    pub fn utilisation<Q>(&self, identifier: D, interval: Q) -> UtilisationStats<I::Distance>
+    where
+        Q: IntervalType<I>,
+        I: PointArithmetic,
+    {
+        let mut stats = UtilisationStats::new();
+        for busy in self.inner.gaps_trimmed(
+            |segment: &Segment<D>| segment.identifiers.contains(&identifier),
+            interval,
+        ) {
+            stats.add_busy(I::length(busy.start(), busy.end()));
+        }
+
+        stats
+    }
+
+    /// Gets the [`utilisation()`](NoGapsRefGapQueryIntervalTree::utilisation)
+    /// of every identifier present within the given interval in a
+    /// single pass.
+    ///
+    /// ```
+    /// use std::collections::{BTreeMap, BTreeSet};
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// 	UtilisationStats,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([5, 9]), ii(12, 28));
+    ///
+    /// assert_eq!(
+    /// 	tree.utilisation_all(ii(0, 20)),
+    /// 	BTreeMap::from([
+    /// 		(5, tree.utilisation(5, ii(0, 20))),
+    /// 		(
+    /// 			9,
+    /// 			UtilisationStats {
+    /// 				covered: 9,
+    /// 				busy_intervals: 1,
+    /// 				longest_busy: 9_u32,
+    /// 			}
+    /// 		),
+    /// 	])
+    /// );
+    /// ```
+    pub fn utilisation_all<Q>(&self, interval: Q) -> BTreeMap<D, UtilisationStats<I::Distance>>
+    where
+        Q: IntervalType<I>,
+        I: PointArithmetic,
+    {
+        //the stats of each identifier along with its current busy
+        //interval, which is only added to the stats once it ends
+        let mut all: BTreeMap<D, (UtilisationStats<I::Distance>, K)> = BTreeMap::new();
+
+        for (segment, value) in self.inner.overlapping(interval) {
+            let segment = K::from(ii(
+                Ord::max(segment.start(), interval.start()),
+                Ord::min(segment.end(), interval.end()),
+            ));
+
+            for identifier in value.identifiers.iter() {
+                match all.get_mut(identifier) {
+                    Some((stats, busy)) => {
+                        if touches_ordered(*busy, segment) {
+                            *busy = K::from(merge_ordered(*busy, segment));
+                        } else {
+                            stats.add_busy(I::length(busy.start(), busy.end()));
+                            *busy = segment;
+                        }
+                    }
+                    None => {
+                        all.insert(*identifier, (UtilisationStats::new(), segment));
+                    }
+                }
+            }
+        }
+
+        all.into_iter()
+            .map(|(identifier, (mut stats, busy))| {
+                stats.add_busy(I::length(busy.start(), busy.end()));
+                (identifier, stats)
             })
-            .next()
+            .collect()
+    }
+
+    /// Gets the grid-aligned intervals contained in the gaps from
+    /// [`gap_query()`](GapQueryIntervalTree::gap_query), where the
+    /// grid is made up of slots of length `step` starting from
+    /// `grid_origin`.
+    ///
+    /// Each gap produces at most one interval made up of as many
+    /// whole slots as it contains, if that is at least `min_slots`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `step` is zero.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(-10, 6));
+    /// tree.insert(BTreeSet::from([9]), ii(15, 28));
+    /// tree.insert(BTreeSet::from([2]), ii(40, 60));
+    ///
+    /// //slots of 5 starting from 0
+    /// assert_eq!(
+    /// 	tree.aligned_gaps(None, ii(0, 50), 0_i32, 5, 1)
+    /// 		.collect::<Vec<_>>(),
+    /// 	Vec::from([ii(10, 14), ii(30, 39)])
+    /// );
+    /// assert_eq!(
+    /// 	tree.aligned_gaps(None, ii(0, 50), 0_i32, 5, 2)
+    /// 		.collect::<Vec<_>>(),
+    /// 	Vec::from([ii(30, 39)])
+    /// );
+    /// ```
+    pub fn aligned_gaps<'a, Q>(
+        &'a self,
+        with_identifier: Option<D>,
+        interval: Q,
+        grid_origin: I,
+        step: I::Distance,
+        min_slots: I::Distance,
+    ) -> impl Iterator<Item = K> + 'a
+    where
+        Q: IntervalType<I> + 'a,
+        I: PointArithmetic,
+        D: 'a,
+    {
+        let zero = I::Distance::from(0);
+        let one = I::Distance::from(1);
+        assert!(step > zero);
+
+        self.gaps(
+            move |other_identifiers| valid_identifier(with_identifier, other_identifiers),
+            interval,
+        )
+        .filter_map(move |gap| {
+            //the first grid point in the gap
+            let start = if gap.start() >= grid_origin {
+                let remainder = gap.start().distance(grid_origin) % step;
+                gap.start()
+                    .checked_add_distance((step - remainder) % step)?
+            } else {
+                let remainder = grid_origin.distance(gap.start()) % step;
+                gap.start().checked_add_distance(remainder)?
+            };
+            if start > gap.end() {
+                return None;
+            }
+
+            //the slots from start up to and including the end of the
+            //gap, written so as not to overflow for full-domain gaps
+            //which can contain one more slot than is representable
+            let distance = gap.end().distance(start);
+            let whole_slots = distance / step;
+            let ends_on_slot = distance % step + one == step;
+
+            let (enough_slots, offset) = if ends_on_slot {
+                (min_slots <= one || whole_slots >= min_slots - one, distance)
+            } else if whole_slots == zero {
+                (false, zero)
+            } else {
+                (whole_slots >= min_slots, whole_slots * step - one)
+            };
+            if !enough_slots {
+                return None;
+            }
+
+            let end = start.checked_add_distance(offset)?;
+
+            Some(K::from(ii(start, end)))
+        })
+    }
+
+    /// Gets the maximally-sized gaps that overlap the given interval
+    /// for a requester of the given `priority`, where a point is free
+    /// if every identifier present, other than the given identifier
+    /// if one is given, has a strictly lower priority according to
+    /// `priorities`.
+    ///
+    /// Each gap is returned with the identifiers in it which would be
+    /// preempted.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, NoGapsRefGapQueryIntervalTree,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([5]), ii(3, 6));
+    /// tree.insert(BTreeSet::from([1]), ii(5, 10));
+    /// tree.insert(BTreeSet::from([9]), ii(12, 28));
+    ///
+    /// //the identifiers are their own priorities
+    /// assert_eq!(
+    /// 	tree.preemptible_gap_query(None, 4, |x| *x, ii(8, 8)),
+    /// 	Vec::from([(ii(7, 11), BTreeSet::from([1]))])
+    /// );
+    /// assert_eq!(
+    /// 	tree.preemptible_gap_query(Some(9), 6, |x| *x, ii(8, 8)),
+    /// 	Vec::from([(ii(i32::MIN, i32::MAX), BTreeSet::from([1, 5]))])
+    /// );
+    /// ```
+    pub fn preemptible_gap_query<Q, F, P>(
+        &self,
+        with_identifier: Option<D>,
+        priority: P,
+        priorities: F,
+        interval: Q,
+    ) -> Vec<(K, BTreeSet<D>)>
+    where
+        Q: IntervalType<I>,
+        F: Fn(&D) -> P,
+        P: Ord,
+    {
+        let preemptible = |identifier: &D| {
+            Some(*identifier) == with_identifier || priorities(identifier) < priority
+        };
+
+        self.gap_query_by(
+            |other_identifiers| other_identifiers.iter().all(preemptible),
+            interval,
+        )
+        .into_iter()
+        .map(|gap| {
+            let preempted = self
+                .inner
+                .overlapping(gap)
+                .flat_map(|(_, segment)| &segment.identifiers)
+                .filter(|identifier| Some(**identifier) != with_identifier)
+                .copied()
+                .collect();
+
+            (gap, preempted)
+        })
+        .collect()
+    }
+}
+
+impl<I, K, D> NoGapsRefGapQueryIntervalTree<I, K, D>
+where
+    I: PointType,
+    K: IntervalType<I>,
+    D: IdType,
+{
+    fn get_gaps_with_identifier<Q>(&self, identifier: D, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.gap_query_by(
+            |other_identifiers| valid_identifier(Some(identifier), other_identifiers),
+            interval,
+        )
+    }
+    fn get_gaps_no_identifier<Q>(&self, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.gap_query_by(BTreeSet::is_empty, interval)
     }
 
     pub(crate) fn into_naive(self) -> NaiveGapQueryIntervalTree<I, K, D> {
         let mut naive = NaiveGapQueryIntervalTree::new();
 
-        for (interval, identifiers) in self.inner {
-            for identifier in identifiers {
+        for (interval, segment) in self.inner.into_segments() {
+            for identifier in segment.identifiers {
                 naive
                     .inner
                     .entry(identifier)
@@ -248,6 +1714,14 @@ where
                     .insert_merge_touching(interval)
                     .unwrap();
             }
+            for identifier in segment.soft {
+                naive
+                    .soft
+                    .entry(identifier)
+                    .or_default()
+                    .insert_merge_touching(interval)
+                    .unwrap();
+            }
         }
 
         naive
@@ -266,39 +1740,12 @@ where
         None => other_identifiers.is_empty(),
     }
 }
-/// Requires that self comes before other
-fn merge_ordered<I, A, B>(a: A, b: B) -> Interval<I>
-where
-    I: PointType,
-    A: IntervalType<I>,
-    B: IntervalType<I>,
-{
-    ii(a.start(), b.end())
-}
-/// Requires that self comes before other
-fn overlaps_ordered<I, A, B>(a: A, b: B) -> bool
-where
-    I: PointType,
-    A: IntervalType<I>,
-    B: IntervalType<I>,
-{
-    a.contains(b.start()) || a.contains(b.end())
-}
-/// Requires that self comes before other
-fn touches_ordered<I, A, B>(a: A, b: B) -> bool
-where
-    I: PointType,
-    A: IntervalType<I>,
-    B: IntervalType<I>,
-{
-    a.end() == b.start().down().unwrap()
-}
 
 impl<I, K, D> PartialEq for NoGapsRefGapQueryIntervalTree<I, K, D>
 where
     I: PartialEq,
     K: PartialEq,
-    BTreeSet<D>: PartialEq,
+    D: PartialEq,
 {
     fn eq(&self, other: &Self) -> bool {
         self.inner.eq(&other.inner)
@@ -311,10 +1758,9 @@ where
     K: IntervalType<I>,
 {
     fn default() -> Self {
-        let mut map = NoditMap::new();
-        map.insert_strict(K::from(uu()), BTreeSet::new())
-            .unwrap_or_else(|_| panic!());
-        Self { inner: map }
+        Self {
+            inner: SegmentMap::new(),
+        }
     }
 }
 
@@ -0,0 +1,238 @@
+/*
+   Copyright 2023 James Forster
+
+   This file is part of gap_query_interval_tree.
+
+   gap_query_interval_tree is free software: you can redistribute it
+   and/or modify it under the terms of the GNU Affero General Public
+   License as published by the Free Software Foundation, either
+   version 3 of the License, or (at your option) any later version.
+
+   gap_query_interval_tree is distributed in the hope that it will be
+   useful, but WITHOUT ANY WARRANTY; without even the implied warranty
+   of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+   Affero General Public License for more details.
+
+   You should have received a copy of the GNU Affero General Public
+   License along with gap_query_interval_tree. If not, see
+   <https://www.gnu.org/licenses/>.
+*/
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use nodit::{IntervalType, PointType};
+use serde::{Deserialize, Serialize};
+
+use crate::segment_map::SegmentMap;
+use crate::IdType;
+
+/// A variant of
+/// [`NoGapsRefGapQueryIntervalTree`](crate::NoGapsRefGapQueryIntervalTree)
+/// whose segments map each identifier to a payload rather than just
+/// recording its presence.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayloadIntervalTree<I, K, D, V> {
+    #[serde(bound(
+        deserialize = "I: PointType, K: IntervalType<I> + Deserialize<'de>, D: IdType + Deserialize<'de>, V: Deserialize<'de>,"
+    ))]
+    inner: SegmentMap<I, K, BTreeMap<D, V>>,
+}
+
+impl<I, K, D, V> PayloadIntervalTree<I, K, D, V>
+where
+    I: PointType,
+    K: IntervalType<I>,
+    D: IdType,
+    V: Clone + Eq,
+{
+    /// Inserts an interval into the collection for the given
+    /// identifiers with their payloads.
+    ///
+    /// Wherever an identifier already has a payload the two are
+    /// combined with `merge(existing, new)`.
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::PayloadIntervalTree;
+    ///
+    /// let mut tree = PayloadIntervalTree::new();
+    /// tree.insert(BTreeMap::from([(5, 1)]), ii(3, 6), |a, b| a + b);
+    /// tree.insert(BTreeMap::from([(5, 2)]), ii(5, 8), |a, b| a + b);
+    ///
+    /// assert_eq!(tree.payloads_at_point(4), BTreeMap::from([(5, 1)]));
+    /// assert_eq!(tree.payloads_at_point(6), BTreeMap::from([(5, 3)]));
+    /// assert_eq!(tree.payloads_at_point(8), BTreeMap::from([(5, 2)]));
+    /// ```
+    pub fn insert<F>(&mut self, payloads: BTreeMap<D, V>, interval: K, mut merge: F)
+    where
+        F: FnMut(&V, &V) -> V,
+    {
+        self.update(interval, |existing_payloads| {
+            for (identifier, payload) in payloads.iter() {
+                match existing_payloads.get_mut(identifier) {
+                    Some(existing) => *existing = merge(existing, payload),
+                    None => {
+                        existing_payloads.insert(*identifier, payload.clone());
+                    }
+                }
+            }
+        });
+    }
+
+    /// Cuts an interval from the collection for the given
+    /// identifiers, if no identifiers are given all identifiers are
+    /// cut.
+    ///
+    /// ```
+    /// use std::collections::{BTreeMap, BTreeSet};
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::PayloadIntervalTree;
+    ///
+    /// let mut tree = PayloadIntervalTree::new();
+    /// tree.insert(BTreeMap::from([(5, 'a'), (9, 'b')]), ii(3, 6), |_, b| *b);
+    ///
+    /// tree.cut(Some(BTreeSet::from([5])), ii(4, 5));
+    ///
+    /// assert_eq!(tree.payloads_at_point(4), BTreeMap::from([(9, 'b')]));
+    /// assert_eq!(
+    /// 	tree.payloads_at_point(6),
+    /// 	BTreeMap::from([(5, 'a'), (9, 'b')])
+    /// );
+    /// ```
+    pub fn cut<Q>(&mut self, with_identifiers: Option<BTreeSet<D>>, interval: Q)
+    where
+        Q: IntervalType<I>,
+    {
+        self.update(interval, |payloads| match with_identifiers.as_ref() {
+            Some(identifiers) => payloads.retain(|i, _| !identifiers.contains(i)),
+            None => payloads.clear(),
+        });
+    }
+
+    /// Append one interval tree with another by inserting all the
+    /// intervals from `other` into `self`, combining overlapping
+    /// payloads with `merge(self_payload, other_payload)`.
+    pub fn append<F>(&mut self, other: &mut Self, mut merge: F)
+    where
+        F: FnMut(&V, &V) -> V,
+    {
+        for (interval, payloads) in core::mem::take(&mut other.inner).into_segments() {
+            self.insert(payloads, interval, &mut merge);
+        }
+    }
+
+    /// Get the payloads of all identifiers which have an interval
+    /// overlapping the given point.
+    pub fn payloads_at_point(&self, at_point: I) -> BTreeMap<D, V> {
+        self.inner.get_at_point(at_point).clone()
+    }
+
+    /// Get the payloads of every segment overlapping the given
+    /// interval, segments with no identifiers are skipped.
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::PayloadIntervalTree;
+    ///
+    /// let mut tree = PayloadIntervalTree::new();
+    /// tree.insert(BTreeMap::from([(5, 'a')]), ii(3, 6), |_, b| *b);
+    /// tree.insert(BTreeMap::from([(9, 'b')]), ii(5, 8), |_, b| *b);
+    ///
+    /// assert_eq!(
+    /// 	tree.payloads_overlapping(ii(0, 5)),
+    /// 	Vec::from([
+    /// 		(ii(3, 4), BTreeMap::from([(5, 'a')])),
+    /// 		(ii(5, 6), BTreeMap::from([(5, 'a'), (9, 'b')])),
+    /// 	])
+    /// );
+    /// ```
+    pub fn payloads_overlapping<Q>(&self, interval: Q) -> Vec<(K, BTreeMap<D, V>)>
+    where
+        Q: IntervalType<I>,
+    {
+        self.inner
+            .overlapping(interval)
+            .filter(|(_, payloads)| !payloads.is_empty())
+            .map(|(inner_interval, payloads)| (*inner_interval, payloads.clone()))
+            .collect()
+    }
+
+    /// Get the payloads of the given identifier for every segment
+    /// overlapping the given interval in which it is present.
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::PayloadIntervalTree;
+    ///
+    /// let mut tree = PayloadIntervalTree::new();
+    /// tree.insert(BTreeMap::from([(5, 'a')]), ii(3, 6), |_, b| *b);
+    /// tree.insert(BTreeMap::from([(9, 'b')]), ii(5, 8), |_, b| *b);
+    ///
+    /// assert_eq!(
+    /// 	tree.identifier_payloads_overlapping(9, ii(0, 10)),
+    /// 	Vec::from([(ii(5, 6), 'b'), (ii(7, 8), 'b')])
+    /// );
+    /// ```
+    pub fn identifier_payloads_overlapping<Q>(&self, identifier: D, interval: Q) -> Vec<(K, V)>
+    where
+        Q: IntervalType<I>,
+    {
+        self.inner
+            .overlapping(interval)
+            .filter_map(|(inner_interval, payloads)| {
+                payloads
+                    .get(&identifier)
+                    .map(|payload| (*inner_interval, payload.clone()))
+            })
+            .collect()
+    }
+
+    fn update<Q, F>(&mut self, interval: Q, f: F)
+    where
+        Q: IntervalType<I>,
+        F: FnMut(&mut BTreeMap<D, V>),
+    {
+        self.inner.update(interval, f);
+    }
+}
+
+impl<I, K, D, V> PartialEq for PayloadIntervalTree<I, K, D, V>
+where
+    I: PartialEq,
+    K: PartialEq,
+    BTreeMap<D, V>: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.inner.eq(&other.inner)
+    }
+}
+
+impl<I, K, D, V> Default for PayloadIntervalTree<I, K, D, V>
+where
+    I: PointType,
+    K: IntervalType<I>,
+{
+    fn default() -> Self {
+        Self {
+            inner: SegmentMap::new(),
+        }
+    }
+}
+
+impl<I, K, D, V> PayloadIntervalTree<I, K, D, V>
+where
+    I: PointType,
+    K: IntervalType<I>,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,261 @@
+/*
+   Copyright 2023 James Forster
+
+   This file is part of gap_query_interval_tree.
+
+   gap_query_interval_tree is free software: you can redistribute it
+   and/or modify it under the terms of the GNU Affero General Public
+   License as published by the Free Software Foundation, either
+   version 3 of the License, or (at your option) any later version.
+
+   gap_query_interval_tree is distributed in the hope that it will be
+   useful, but WITHOUT ANY WARRANTY; without even the implied warranty
+   of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+   Affero General Public License for more details.
+
+   You should have received a copy of the GNU Affero General Public
+   License along with gap_query_interval_tree. If not, see
+   <https://www.gnu.org/licenses/>.
+*/
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use nodit::interval::ii;
+use nodit::{IntervalType, NoditSet};
+
+use crate::interface::GapQueryIntervalTree;
+use crate::no_gaps_ref::NoGapsRefGapQueryIntervalTree;
+use crate::point_arithmetic::PointArithmetic;
+use crate::IdType;
+
+/// A job to be placed by
+/// [`place_jobs()`](NoGapsRefGapQueryIntervalTree::place_jobs).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Job<I, L> {
+    /// The number of points the job needs, which must not be zero.
+    pub length: L,
+    /// The earliest point the job may start at.
+    pub earliest_start: I,
+    /// The latest point the job may end at, if any.
+    pub deadline: Option<I>,
+}
+
+/// How [`place_jobs()`](NoGapsRefGapQueryIntervalTree::place_jobs)
+/// chooses where to place each job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PlacementStrategy {
+    /// Jobs are placed in the given order, each at the earliest point
+    /// it fits.
+    FirstFit,
+    /// Jobs are placed in the given order, each in the gap it leaves
+    /// the fewest points free in, at the earliest point it fits in
+    /// that gap.
+    BestFit,
+    /// Jobs are placed in order of their deadlines, with jobs without
+    /// a deadline last, each at the earliest point it fits.
+    EarliestDeadline,
+}
+
+/// The error returned when a job could not be placed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PlacementError {
+    /// The index of the first job which could not be placed.
+    pub job: usize,
+}
+
+impl<I, K, D> NoGapsRefGapQueryIntervalTree<I, K, D>
+where
+    I: PointArithmetic,
+    K: IntervalType<I>,
+    D: IdType,
+{
+    /// Finds a placement for each of the given jobs in the free time
+    /// of the given identifier, without the jobs overlapping each
+    /// other.
+    ///
+    /// The free time of an identifier is wherever it is not present
+    /// and there is no blackout, regardless of any other identifiers,
+    /// like [`common_free()`](NoGapsRefGapQueryIntervalTree::common_free)
+    /// of just that identifier.
+    ///
+    /// The placements are returned in the same order as the jobs, the
+    /// tree is not modified, see
+    /// [`insert_jobs()`](NoGapsRefGapQueryIntervalTree::insert_jobs)
+    /// for that.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any job has a length of zero.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, Job, NoGapsRefGapQueryIntervalTree,
+    /// 	PlacementError, PlacementStrategy,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([9]), ii(0, 9));
+    /// tree.insert(BTreeSet::from([9]), ii(20, 21));
+    /// tree.insert(BTreeSet::from([9]), ii(25, 40));
+    /// //other identifiers do not take up 9's free time
+    /// tree.insert(BTreeSet::from([5]), ii(10, 19));
+    ///
+    /// //9's free time before the deadline is 10..=19 and 22..=24
+    /// let jobs = [
+    /// 	Job {
+    /// 		length: 3_u32,
+    /// 		earliest_start: 0,
+    /// 		deadline: Some(30),
+    /// 	},
+    /// 	Job {
+    /// 		length: 8,
+    /// 		earliest_start: 0,
+    /// 		deadline: Some(30),
+    /// 	},
+    /// ];
+    ///
+    /// assert_eq!(
+    /// 	tree.place_jobs(9, jobs, PlacementStrategy::FirstFit),
+    /// 	Err(PlacementError { job: 1 })
+    /// );
+    /// assert_eq!(
+    /// 	tree.place_jobs(9, jobs, PlacementStrategy::BestFit),
+    /// 	Ok(Vec::from([ii(22, 24), ii(10, 17)]))
+    /// );
+    /// ```
+    pub fn place_jobs<T>(
+        &self,
+        identifier: D,
+        jobs: T,
+        strategy: PlacementStrategy,
+    ) -> Result<Vec<K>, PlacementError>
+    where
+        T: IntoIterator<Item = Job<I, I::Distance>>,
+    {
+        let jobs = jobs.into_iter().collect::<Vec<_>>();
+
+        let mut order = (0..jobs.len()).collect::<Vec<_>>();
+        if strategy == PlacementStrategy::EarliestDeadline {
+            //a stable sort so equal deadlines keep the given order
+            order.sort_by_key(|job| (jobs[*job].deadline.is_none(), jobs[*job].deadline));
+        }
+
+        //the placements so far which later jobs must avoid
+        let mut placed: NoditSet<I, K> = NoditSet::new();
+        let mut placements = Vec::from_iter(core::iter::repeat_n(None, jobs.len()));
+
+        for job in order {
+            let placement = self
+                .place_job(identifier, &jobs[job], strategy, &placed)
+                .ok_or(PlacementError { job })?;
+
+            placed.insert_strict(placement).unwrap_or_else(|_| panic!());
+            placements[job] = Some(placement);
+        }
+
+        Ok(placements.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Like [`place_jobs()`](NoGapsRefGapQueryIntervalTree::place_jobs)
+    /// but the placements are also inserted for the given identifier.
+    ///
+    /// This is atomic: if any job could not be placed then nothing is
+    /// inserted.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::{
+    /// 	GapQueryIntervalTree, Job, NoGapsRefGapQueryIntervalTree,
+    /// 	PlacementError, PlacementStrategy,
+    /// };
+    ///
+    /// let mut tree = NoGapsRefGapQueryIntervalTree::new();
+    /// tree.insert(BTreeSet::from([9]), ii(0, 9));
+    ///
+    /// let job = Job {
+    /// 	length: 4_u32,
+    /// 	earliest_start: 0,
+    /// 	deadline: Some(15),
+    /// };
+    ///
+    /// assert_eq!(
+    /// 	tree.insert_jobs(9, [job, job], PlacementStrategy::EarliestDeadline),
+    /// 	Err(PlacementError { job: 1 })
+    /// );
+    /// assert_eq!(tree.identifiers_at_point(10), BTreeSet::from([]));
+    ///
+    /// assert_eq!(
+    /// 	tree.insert_jobs(9, [job], PlacementStrategy::EarliestDeadline),
+    /// 	Ok(Vec::from([ii(10, 13)]))
+    /// );
+    /// assert_eq!(tree.identifiers_at_point(10), BTreeSet::from([9]));
+    ///
+    /// //9 is now present at 10..=13 too so only 14..=15 is free
+    /// assert_eq!(
+    /// 	tree.insert_jobs(9, [job], PlacementStrategy::EarliestDeadline),
+    /// 	Err(PlacementError { job: 0 })
+    /// );
+    /// ```
+    pub fn insert_jobs<T>(
+        &mut self,
+        identifier: D,
+        jobs: T,
+        strategy: PlacementStrategy,
+    ) -> Result<Vec<K>, PlacementError>
+    where
+        T: IntoIterator<Item = Job<I, I::Distance>>,
+    {
+        let placements = self.place_jobs(identifier, jobs, strategy)?;
+
+        for placement in placements.iter() {
+            self.insert(BTreeSet::from([identifier]), *placement);
+        }
+
+        Ok(placements)
+    }
+
+    fn place_job(
+        &self,
+        identifier: D,
+        job: &Job<I, I::Distance>,
+        strategy: PlacementStrategy,
+        placed: &NoditSet<I, K>,
+    ) -> Option<K> {
+        let one = I::Distance::from(1);
+        assert!(job.length >= one);
+
+        let window_end = job.deadline.unwrap_or(I::MAX);
+        if job.earliest_start > window_end {
+            return None;
+        }
+
+        //the free intervals within the window not already taken by
+        //another job in order
+        let mut free = self
+            .free_time_trimmed(identifier, ii(job.earliest_start, window_end))
+            .flat_map(|gap| placed.gaps_trimmed(gap).collect::<Vec<_>>())
+            .filter(|free| I::length(free.start(), free.end()) >= job.length);
+
+        let chosen = match strategy {
+            PlacementStrategy::FirstFit | PlacementStrategy::EarliestDeadline => free.next(),
+            //min_by_key returns the first of equal minimums so ties
+            //go to the earliest
+            PlacementStrategy::BestFit => {
+                free.min_by_key(|free| I::length(free.start(), free.end()) - job.length)
+            }
+        }?;
+
+        let end = chosen
+            .start()
+            .checked_add_distance(job.length - one)
+            .unwrap();
+
+        Some(K::from(ii(chosen.start(), end)))
+    }
+}
@@ -0,0 +1,104 @@
+/*
+   Copyright 2023 James Forster
+
+   This file is part of gap_query_interval_tree.
+
+   gap_query_interval_tree is free software: you can redistribute it
+   and/or modify it under the terms of the GNU Affero General Public
+   License as published by the Free Software Foundation, either
+   version 3 of the License, or (at your option) any later version.
+
+   gap_query_interval_tree is distributed in the hope that it will be
+   useful, but WITHOUT ANY WARRANTY; without even the implied warranty
+   of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+   Affero General Public License for more details.
+
+   You should have received a copy of the GNU Affero General Public
+   License along with gap_query_interval_tree. If not, see
+   <https://www.gnu.org/licenses/>.
+*/
+
+use core::ops::{Add, Div, Mul, Rem, Sub};
+
+use nodit::PointType;
+
+/// Arithmetic on points for queries which need to offset them, such
+/// as [`gap_query_padded()`](crate::GapQueryIntervalTree::gap_query_padded).
+///
+/// This is implemented for the same primitive integer types as
+/// [`DiscreteFinite`](nodit::DiscreteFinite), custom point types such
+/// as timestamps can implement it with their own duration type.
+pub trait PointArithmetic: PointType {
+    /// The type of the distance between two points.
+    type Distance: Ord
+        + Copy
+        + From<u8>
+        + Add<Output = Self::Distance>
+        + Sub<Output = Self::Distance>
+        + Mul<Output = Self::Distance>
+        + Div<Output = Self::Distance>
+        + Rem<Output = Self::Distance>;
+
+    /// The absolute distance between `self` and `other`.
+    fn distance(self, other: Self) -> Self::Distance;
+    /// The number of points from `start` to `end` inclusive,
+    /// saturating at the largest distance, which only occurs for
+    /// an interval covering every point.
+    fn length(start: Self, end: Self) -> Self::Distance;
+
+    /// `self + distance` if it is representable.
+    fn checked_add_distance(self, distance: Self::Distance) -> Option<Self>;
+    /// `self - distance` if it is representable.
+    fn checked_sub_distance(self, distance: Self::Distance) -> Option<Self>;
+}
+
+macro_rules! unsigned {
+    ($($t:ident),*) => {
+        $(
+            impl PointArithmetic for $t {
+                type Distance = $t;
+
+                fn distance(self, other: Self) -> Self::Distance {
+                    self.abs_diff(other)
+                }
+                fn length(start: Self, end: Self) -> Self::Distance {
+                    end.abs_diff(start).saturating_add(1)
+                }
+
+                fn checked_add_distance(self, distance: Self::Distance) -> Option<Self> {
+                    self.checked_add(distance)
+                }
+                fn checked_sub_distance(self, distance: Self::Distance) -> Option<Self> {
+                    self.checked_sub(distance)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! signed {
+    ($($t:ident => $d:ident),*) => {
+        $(
+            impl PointArithmetic for $t {
+                type Distance = $d;
+
+                fn distance(self, other: Self) -> Self::Distance {
+                    self.abs_diff(other)
+                }
+                fn length(start: Self, end: Self) -> Self::Distance {
+                    end.abs_diff(start).saturating_add(1)
+                }
+
+                fn checked_add_distance(self, distance: Self::Distance) -> Option<Self> {
+                    self.checked_add_unsigned(distance)
+                }
+                fn checked_sub_distance(self, distance: Self::Distance) -> Option<Self> {
+                    self.checked_sub_unsigned(distance)
+                }
+            }
+        )*
+    };
+}
+
+unsigned!(u8, u16, u32, u64, u128, usize);
+signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
@@ -0,0 +1,203 @@
+/*
+   Copyright 2023 James Forster
+
+   This file is part of gap_query_interval_tree.
+
+   gap_query_interval_tree is free software: you can redistribute it
+   and/or modify it under the terms of the GNU Affero General Public
+   License as published by the Free Software Foundation, either
+   version 3 of the License, or (at your option) any later version.
+
+   gap_query_interval_tree is distributed in the hope that it will be
+   useful, but WITHOUT ANY WARRANTY; without even the implied warranty
+   of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+   Affero General Public License for more details.
+
+   You should have received a copy of the GNU Affero General Public
+   License along with gap_query_interval_tree. If not, see
+   <https://www.gnu.org/licenses/>.
+*/
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use nodit::{IntervalType, PointType};
+use serde::{Deserialize, Serialize};
+
+use crate::segment_map::SegmentMap;
+use crate::IdType;
+
+/// An opaque handle to a reservation made with
+/// [`ReservationIntervalTree::reserve()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ReservationHandle(u64);
+
+/// A variant of
+/// [`NoGapsRefGapQueryIntervalTree`](crate::NoGapsRefGapQueryIntervalTree)
+/// where intervals are inserted as reservations which can later be
+/// released individually.
+///
+/// Every segment counts how many live reservations each identifier
+/// has over it, an identifier is present wherever its count is
+/// non-zero, so releasing a reservation only removes an identifier
+/// from the points which no other reservation still covers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReservationIntervalTree<I, K, D> {
+    #[serde(bound(
+        deserialize = "I: PointType, K: IntervalType<I> + Deserialize<'de>, D: IdType + Deserialize<'de>,"
+    ))]
+    inner: SegmentMap<I, K, BTreeMap<D, usize>>,
+    #[serde(bound(deserialize = "K: Deserialize<'de>, D: IdType + Deserialize<'de>,"))]
+    reservations: BTreeMap<ReservationHandle, (BTreeSet<D>, K)>,
+    next_handle: u64,
+}
+
+impl<I, K, D> ReservationIntervalTree<I, K, D>
+where
+    I: PointType,
+    K: IntervalType<I>,
+    D: IdType,
+{
+    /// Reserves an interval for the given identifiers, returning a
+    /// handle which can be passed to
+    /// [`release()`](ReservationIntervalTree::release) to undo
+    /// exactly this reservation.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::ReservationIntervalTree;
+    ///
+    /// let mut tree = ReservationIntervalTree::new();
+    /// let first = tree.reserve(BTreeSet::from([5]), ii(3, 6));
+    /// let second = tree.reserve(BTreeSet::from([5]), ii(5, 8));
+    ///
+    /// tree.release(first);
+    ///
+    /// assert_eq!(
+    /// 	tree.gap_query(None, ii(0, 10)),
+    /// 	Vec::from([ii(i32::MIN, 4), ii(9, i32::MAX)])
+    /// );
+    /// ```
+    pub fn reserve(&mut self, identifiers: BTreeSet<D>, interval: K) -> ReservationHandle {
+        let handle = ReservationHandle(self.next_handle);
+        self.next_handle += 1;
+
+        self.inner.update(interval, |counts| {
+            for identifier in identifiers.iter() {
+                *counts.entry(*identifier).or_default() += 1;
+            }
+        });
+
+        self.reservations.insert(handle, (identifiers, interval));
+
+        handle
+    }
+
+    /// Releases a reservation previously made with
+    /// [`reserve()`](ReservationIntervalTree::reserve), returning the
+    /// identifiers and interval it was made with, or `None` if it had
+    /// already been released.
+    ///
+    /// Identifiers are only removed from the points that no other
+    /// live reservation of theirs still covers.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::ReservationIntervalTree;
+    ///
+    /// let mut tree = ReservationIntervalTree::new();
+    /// let first = tree.reserve(BTreeSet::from([5]), ii(3, 6));
+    /// let second = tree.reserve(BTreeSet::from([5, 9]), ii(5, 8));
+    ///
+    /// assert_eq!(
+    /// 	tree.release(second),
+    /// 	Some((BTreeSet::from([5, 9]), ii(5, 8)))
+    /// );
+    /// assert_eq!(tree.release(second), None);
+    ///
+    /// assert_eq!(tree.identifiers_at_point(6), BTreeSet::from([5]));
+    /// assert_eq!(tree.identifiers_at_point(7), BTreeSet::from([]));
+    /// ```
+    pub fn release(&mut self, handle: ReservationHandle) -> Option<(BTreeSet<D>, K)> {
+        let (identifiers, interval) = self.reservations.remove(&handle)?;
+
+        self.inner.update(interval, |counts| {
+            for identifier in identifiers.iter() {
+                if let Some(count) = counts.get_mut(identifier) {
+                    *count -= 1;
+                    //so that presence is just having an entry
+                    if *count == 0 {
+                        counts.remove(identifier);
+                    }
+                }
+            }
+        });
+
+        Some((identifiers, interval))
+    }
+
+    /// Get the identifiers and interval of a reservation that has not
+    /// yet been released.
+    pub fn reservation(&self, handle: ReservationHandle) -> Option<&(BTreeSet<D>, K)> {
+        self.reservations.get(&handle)
+    }
+
+    /// Gets the maximally-sized gaps that overlap the given interval
+    /// for the given identifier if one is given, with the same
+    /// semantics as
+    /// [`gap_query()`](crate::GapQueryIntervalTree::gap_query).
+    pub fn gap_query<Q>(&self, with_identifier: Option<D>, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+    {
+        self.inner.gap_query_by(
+            |counts| match with_identifier {
+                Some(identifier) => {
+                    counts.is_empty() || (counts.len() == 1 && counts.contains_key(&identifier))
+                }
+                None => counts.is_empty(),
+            },
+            interval,
+        )
+    }
+
+    /// Get all identifiers with a live reservation overlapping the
+    /// given point.
+    pub fn identifiers_at_point(&self, at_point: I) -> BTreeSet<D> {
+        self.inner.get_at_point(at_point).keys().copied().collect()
+    }
+
+    /// Get the number of live reservations each identifier has
+    /// overlapping the given point.
+    pub fn counts_at_point(&self, at_point: I) -> &BTreeMap<D, usize> {
+        self.inner.get_at_point(at_point)
+    }
+}
+
+impl<I, K, D> Default for ReservationIntervalTree<I, K, D>
+where
+    I: PointType,
+    K: IntervalType<I>,
+{
+    fn default() -> Self {
+        Self {
+            inner: SegmentMap::new(),
+            reservations: BTreeMap::new(),
+            next_handle: 0,
+        }
+    }
+}
+
+impl<I, K, D> ReservationIntervalTree<I, K, D>
+where
+    I: PointType,
+    K: IntervalType<I>,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
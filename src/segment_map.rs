@@ -0,0 +1,562 @@
+/*
+   Copyright 2023 James Forster
+
+   This file is part of gap_query_interval_tree.
+
+   gap_query_interval_tree is free software: you can redistribute it
+   and/or modify it under the terms of the GNU Affero General Public
+   License as published by the Free Software Foundation, either
+   version 3 of the License, or (at your option) any later version.
+
+   gap_query_interval_tree is distributed in the hope that it will be
+   useful, but WITHOUT ANY WARRANTY; without even the implied warranty
+   of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+   Affero General Public License for more details.
+
+   You should have received a copy of the GNU Affero General Public
+   License along with gap_query_interval_tree. If not, see
+   <https://www.gnu.org/licenses/>.
+*/
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::ops::Add;
+
+use itertools::Itertools;
+use nodit::interval::{ii, iu, uu};
+use nodit::{DiscreteFinite, Interval, NoditMap};
+use nodit::{IntervalType, PointType};
+use serde::{Deserialize, Serialize};
+
+/// A value with an associative `combine` operation for which
+/// [`Default::default()`] is the identity.
+pub trait Monoid: Default {
+    /// Combine two values, `a.combine(b)` must equal `b` if `a` is the
+    /// default value and vice versa.
+    fn combine(&self, other: &Self) -> Self;
+}
+
+impl<T> Monoid for BTreeSet<T>
+where
+    T: Ord + Clone,
+{
+    fn combine(&self, other: &Self) -> Self {
+        self.union(other).cloned().collect()
+    }
+}
+
+/// A [`Monoid`] which combines values by adding them together, such
+/// as counts or loads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Sum<T>(pub T);
+
+impl<T> Monoid for Sum<T>
+where
+    T: Default + Add<Output = T> + Copy,
+{
+    fn combine(&self, other: &Self) -> Self {
+        Sum(self.0 + other.0)
+    }
+}
+
+/// A [`Monoid`] which combines values by taking the maximum, such as
+/// priorities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Max<T>(pub T);
+
+impl<T> Default for Max<T>
+where
+    T: DiscreteFinite,
+{
+    fn default() -> Self {
+        Max(T::MIN)
+    }
+}
+
+impl<T> Monoid for Max<T>
+where
+    T: DiscreteFinite + Ord + Copy,
+{
+    fn combine(&self, other: &Self) -> Self {
+        Max(Ord::max(self.0, other.0))
+    }
+}
+
+/// A full-domain tiling of intervals to values, this is the structure
+/// underlying
+/// [`NoGapsRefGapQueryIntervalTree`](crate::NoGapsRefGapQueryIntervalTree)
+/// generalised to any segment value.
+///
+/// Every point is covered by exactly one segment, touching segments
+/// always have differing values and a new map starts as a single
+/// segment with the default value.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SegmentMap<I, K, V> {
+    #[serde(bound(
+        deserialize = "I: PointType, K: IntervalType<I> + Deserialize<'de>, V: Deserialize<'de>,"
+    ))]
+    inner: NoditMap<I, K, V>,
+}
+
+impl<I, K, V> SegmentMap<I, K, V>
+where
+    I: PointType,
+    K: IntervalType<I>,
+{
+    /// Modifies the value of every point in the given interval.
+    ///
+    /// ```
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::SegmentMap;
+    ///
+    /// let mut map = SegmentMap::new();
+    /// map.update(ii(3, 6), |x: &mut u8| *x += 2);
+    /// map.update(ii(5, 8), |x| *x *= 3);
+    ///
+    /// assert_eq!(
+    /// 	map.overlapping(ii(0, 10))
+    /// 		.map(|(k, v)| (*k, *v))
+    /// 		.collect::<Vec<_>>(),
+    /// 	Vec::from([
+    /// 		(ii(u8::MIN, 2), 0),
+    /// 		(ii(3, 4), 2),
+    /// 		(ii(5, 6), 6),
+    /// 		(ii(7, u8::MAX), 0),
+    /// 	])
+    /// );
+    /// ```
+    pub fn update<Q, F>(&mut self, interval: Q, mut f: F)
+    where
+        Q: IntervalType<I>,
+        F: FnMut(&mut V),
+        V: Clone + Eq,
+    {
+        //first we cut the overlapping partial segments, modify them
+        //and then insert them back into the map with
+        //insert_merge_touching_if_values_equal to prevent
+        //fragmentation
+        //
+        //optimisation: do this without cutting and re-inserting
+        //using overlapping_mut or something
+        for (cut_interval, mut cut_value) in self
+            .inner
+            .cut(interval)
+            //to soothe the borrow checker
+            .collect::<Vec<_>>()
+        {
+            f(&mut cut_value);
+            self.inner
+                .insert_merge_touching_if_values_equal(cut_interval, cut_value)
+                .unwrap_or_else(|_| panic!());
+        }
+    }
+
+    /// Combines the given value into every point in the given
+    /// interval.
+    ///
+    /// ```
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::segment_map::{Max, SegmentMap};
+    ///
+    /// let mut map = SegmentMap::new();
+    /// map.insert(Max(4), ii(3, 6));
+    /// map.insert(Max(2), ii(5, 8));
+    ///
+    /// assert_eq!(map.get_at_point(5), &Max(4));
+    /// assert_eq!(map.get_at_point(8), &Max(2));
+    /// ```
+    pub fn insert(&mut self, value: V, interval: K)
+    where
+        V: Monoid + Clone + Eq,
+    {
+        self.update(interval, |existing| *existing = existing.combine(&value));
+    }
+
+    /// Combines the values of every segment overlapping the given
+    /// interval.
+    ///
+    /// ```
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::segment_map::{SegmentMap, Sum};
+    ///
+    /// let mut map = SegmentMap::new();
+    /// map.insert(Sum(4), ii(3, 6));
+    /// map.insert(Sum(2), ii(5, 8));
+    ///
+    /// //the segments overlapping are 3..=4, 5..=6 and 7..=8
+    /// assert_eq!(map.fold(ii(4, 7)), Sum(4 + 6 + 2));
+    /// ```
+    pub fn fold<Q>(&self, interval: Q) -> V
+    where
+        Q: IntervalType<I>,
+        V: Monoid,
+    {
+        self.inner
+            .overlapping(interval)
+            .fold(V::default(), |acc, (_, value)| acc.combine(value))
+    }
+
+    /// Gets the maximally-sized intervals that overlap the given
+    /// interval in which every segment's value satisfies `predicate`.
+    ///
+    /// Like
+    /// [`gap_query()`](crate::GapQueryIntervalTree::gap_query) the
+    /// intervals are not trimmed to the given interval.
+    ///
+    /// ```
+    /// use nodit::Interval;
+    /// use nodit::interval::ii;
+    /// use gap_query_interval_tree::segment_map::{SegmentMap, Sum};
+    ///
+    /// let mut map = SegmentMap::new();
+    /// map.insert(Sum(1), ii(3, 6));
+    /// map.insert(Sum(1), ii(5, 8));
+    ///
+    /// assert_eq!(
+    /// 	map.gap_query_by(|load| load.0 < 2, ii(0, 10)),
+    /// 	Vec::from([ii(u8::MIN, 4), ii(7, u8::MAX)])
+    /// );
+    /// ```
+    pub fn gap_query_by<Q, P>(&self, predicate: P, interval: Q) -> Vec<K>
+    where
+        Q: IntervalType<I>,
+        P: Fn(&V) -> bool,
+    {
+        self.gaps(&predicate, interval).collect()
+    }
+
+    /// The lazy version of
+    /// [`gap_query_by()`](SegmentMap::gap_query_by).
+    pub(crate) fn gaps<'a, Q, P>(
+        &'a self,
+        predicate: P,
+        interval: Q,
+    ) -> impl Iterator<Item = K> + 'a
+    where
+        Q: IntervalType<I> + 'a,
+        P: Fn(&V) -> bool + Clone + 'a,
+    {
+        let filter_predicate = predicate.clone();
+
+        self.inner
+            .overlapping(interval)
+            .filter(move |(_, value)| filter_predicate(value))
+            .map(|(segment, _)| *segment)
+            .coalesce(|x, y| {
+                if touches_ordered(x, y) {
+                    Ok(K::from(merge_ordered(x, y)))
+                } else {
+                    Err((x, y))
+                }
+            })
+            .map(move |gap| {
+                //the end gaps may be partial so we expand them
+                //outwardly only, not inwardly
+                let start = if gap.contains(interval.start()) {
+                    self.expand_left(&predicate, gap.start(), I::MIN)
+                } else {
+                    gap.start()
+                };
+                let end = if gap.contains(interval.end()) {
+                    self.expand_right(&predicate, gap.end(), I::MAX)
+                } else {
+                    gap.end()
+                };
+
+                K::from(ii(start, end))
+            })
+    }
+
+    /// Like [`gaps()`](SegmentMap::gaps) but the intervals are trimmed
+    /// to the given interval rather than expanded.
+    pub(crate) fn gaps_trimmed<'a, Q, P>(
+        &'a self,
+        predicate: P,
+        interval: Q,
+    ) -> impl Iterator<Item = K> + 'a
+    where
+        Q: IntervalType<I> + 'a,
+        P: Fn(&V) -> bool + 'a,
+    {
+        self.inner
+            .overlapping(interval)
+            .filter(move |(_, value)| predicate(value))
+            .map(move |(segment, _)| {
+                K::from(ii(
+                    Ord::max(segment.start(), interval.start()),
+                    Ord::min(segment.end(), interval.end()),
+                ))
+            })
+            .coalesce(|x, y| {
+                if touches_ordered(x, y) {
+                    Ok(K::from(merge_ordered(x, y)))
+                } else {
+                    Err((x, y))
+                }
+            })
+    }
+
+    /// Like [`gaps_trimmed()`](SegmentMap::gaps_trimmed) but walking
+    /// leftwards, so the intervals are in descending order.
+    pub(crate) fn gaps_trimmed_rev<'a, Q, P>(
+        &'a self,
+        predicate: P,
+        interval: Q,
+    ) -> impl Iterator<Item = K> + 'a
+    where
+        Q: IntervalType<I> + 'a,
+        P: Fn(&V) -> bool + 'a,
+    {
+        //we are going in reverse since we are going left
+        self.inner
+            .overlapping(interval)
+            .rev()
+            .filter(move |(_, value)| predicate(value))
+            .map(move |(segment, _)| {
+                K::from(ii(
+                    Ord::max(segment.start(), interval.start()),
+                    Ord::min(segment.end(), interval.end()),
+                ))
+            })
+            .coalesce(|x, y| {
+                if touches_ordered(y, x) {
+                    Ok(K::from(merge_ordered(y, x)))
+                } else {
+                    Err((x, y))
+                }
+            })
+    }
+
+    /// Like [`gaps_trimmed()`](SegmentMap::gaps_trimmed) over the
+    /// intersection of `interval` and `horizon` but with the end gaps
+    /// expanded outwardly as far as `horizon`.
+    ///
+    /// Each gap is returned with whether it continues past the start
+    /// and end of `horizon` respectively.
+    pub(crate) fn gaps_within<'a, Q, H, P>(
+        &'a self,
+        predicate: P,
+        interval: Q,
+        horizon: H,
+    ) -> impl Iterator<Item = (K, bool, bool)> + 'a
+    where
+        Q: IntervalType<I>,
+        H: IntervalType<I> + 'a,
+        P: Fn(&V) -> bool + Clone + 'a,
+    {
+        let window_start = Ord::max(interval.start(), horizon.start());
+        let window_end = Ord::min(interval.end(), horizon.end());
+        let window = (window_start <= window_end).then(|| ii(window_start, window_end));
+
+        window.into_iter().flat_map(move |window| {
+            let predicate = predicate.clone();
+
+            self.gaps_trimmed(predicate.clone(), window)
+                .map(move |gap| {
+                    let start = if gap.start() == window.start() {
+                        self.expand_left(&predicate, gap.start(), horizon.start())
+                    } else {
+                        gap.start()
+                    };
+                    let end = if gap.end() == window.end() {
+                        self.expand_right(&predicate, gap.end(), horizon.end())
+                    } else {
+                        gap.end()
+                    };
+
+                    let truncated_left = start == horizon.start()
+                        && start
+                            .down()
+                            .is_some_and(|before| predicate(self.get_at_point(before)));
+                    let truncated_right = end == horizon.end()
+                        && end
+                            .up()
+                            .is_some_and(|after| predicate(self.get_at_point(after)));
+
+                    (K::from(ii(start, end)), truncated_left, truncated_right)
+                })
+        })
+    }
+
+    /// Gets the maximally-sized interval around each of the given
+    /// points in which every segment's value satisfies `predicate`, or
+    /// `None` for points whose segment does not.
+    ///
+    /// This is a single walk from the first point onwards so the
+    /// points must be in ascending order.
+    pub(crate) fn gaps_at_points<'a, T, P>(
+        &'a self,
+        predicate: P,
+        points: T,
+    ) -> impl Iterator<Item = Option<K>> + 'a
+    where
+        T: IntoIterator<Item = I>,
+        T::IntoIter: 'a,
+        P: Fn(&V) -> bool + 'a,
+    {
+        let mut points = points.into_iter().peekable();
+        let mut segments = points
+            .peek()
+            .map(|first| self.inner.overlapping(iu(*first)).peekable());
+        let mut previous: Option<I> = None;
+        let mut gap: Option<K> = None;
+
+        points.map(move |point| {
+            assert!(
+                previous.is_none_or(|previous| previous <= point),
+                "points must be in ascending order"
+            );
+            previous = Some(point);
+
+            if let Some(gap) = gap
+                && gap.contains(point)
+            {
+                return Some(gap);
+            }
+
+            //since the first point starts the walk this is always Some
+            let segments = segments.as_mut().unwrap();
+            while segments
+                .next_if(|(segment, _)| segment.end() < point)
+                .is_some()
+            {}
+
+            //since there are no gaps this segment contains the point
+            let (segment, value) = segments.peek().unwrap();
+            if !predicate(value) {
+                return None;
+            }
+
+            let start = self.expand_left(&predicate, segment.start(), I::MIN);
+            let mut end = segment.end();
+            //later points are either in this gap or after it so its
+            //segments can be consumed
+            while let Some((segment, _)) = segments.next_if(|(_, value)| predicate(value)) {
+                end = segment.end();
+            }
+
+            gap = Some(K::from(ii(start, end)));
+            gap
+        })
+    }
+
+    /// Returns the start of the left-most segment, clamped to
+    /// `limit`, in the run of segments satisfying `predicate` that
+    /// ends just before `point`, or `point` if there is no such run.
+    fn expand_left<P>(&self, predicate: P, point: I, limit: I) -> I
+    where
+        P: Fn(&V) -> bool,
+    {
+        let Some(before) = point.down() else {
+            return point;
+        };
+        if before < limit {
+            return point;
+        }
+
+        //we are going in reverse since we are going left
+        //
+        //since there are no gaps we know they will always touch
+        self.inner
+            .overlapping(ii(limit, before))
+            .rev()
+            .take_while(|(_, value)| predicate(value))
+            .last()
+            .map_or(point, |(segment, _)| Ord::max(segment.start(), limit))
+    }
+    /// Returns the end of the right-most segment, clamped to `limit`,
+    /// in the run of segments satisfying `predicate` that starts just
+    /// after `point`, or `point` if there is no such run.
+    fn expand_right<P>(&self, predicate: P, point: I, limit: I) -> I
+    where
+        P: Fn(&V) -> bool,
+    {
+        let Some(after) = point.up() else {
+            return point;
+        };
+        if after > limit {
+            return point;
+        }
+
+        //since there are no gaps we know they will always touch
+        self.inner
+            .overlapping(ii(after, limit))
+            .take_while(|(_, value)| predicate(value))
+            .last()
+            .map_or(point, |(segment, _)| Ord::min(segment.end(), limit))
+    }
+
+    /// Gets the value of the segment covering the given point.
+    pub fn get_at_point(&self, at_point: I) -> &V {
+        self.inner
+            .get_at_point(at_point)
+            .unwrap_or_else(|| panic!())
+    }
+
+    /// Gets every segment overlapping the given interval in ascending
+    /// order.
+    pub fn overlapping<Q>(&self, interval: Q) -> impl DoubleEndedIterator<Item = (&K, &V)>
+    where
+        Q: IntervalType<I>,
+    {
+        self.inner.overlapping(interval)
+    }
+
+    /// Gets every segment in ascending order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> {
+        self.inner.iter()
+    }
+
+    /// Consumes the map, returning every segment in ascending order.
+    pub fn into_segments(self) -> impl Iterator<Item = (K, V)> {
+        self.inner.into_iter()
+    }
+}
+
+/// Requires that self comes before other
+pub(crate) fn merge_ordered<I, A, B>(a: A, b: B) -> Interval<I>
+where
+    I: PointType,
+    A: IntervalType<I>,
+    B: IntervalType<I>,
+{
+    ii(a.start(), b.end())
+}
+/// Requires that self comes before other
+pub(crate) fn touches_ordered<I, A, B>(a: A, b: B) -> bool
+where
+    I: PointType,
+    A: IntervalType<I>,
+    B: IntervalType<I>,
+{
+    a.end() == b.start().down().unwrap()
+}
+
+impl<I, K, V> Default for SegmentMap<I, K, V>
+where
+    I: PointType,
+    K: IntervalType<I>,
+    V: Default,
+{
+    fn default() -> Self {
+        let mut map = NoditMap::new();
+        map.insert_strict(K::from(uu()), V::default())
+            .unwrap_or_else(|_| panic!());
+        Self { inner: map }
+    }
+}
+
+impl<I, K, V> SegmentMap<I, K, V>
+where
+    I: PointType,
+    K: IntervalType<I>,
+    V: Default,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+}